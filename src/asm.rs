@@ -0,0 +1,214 @@
+// asm.rs
+// Contains the "asm" subcommand, which maps assembly contigs to a reference as colinear blocks (several per contig and reference, on both strands),
+// and the "Block" struct describing one of them.
+
+use crate::{Params, chain::Chain, closures, get_reader, index::{ReadOnlyIndex, RefInfo}, is_fasta_filename, liftover::{self, Segment}, mask::Mask, mers, presets::SeedingOpt, r#match::Match, stats::Stats, summary::Summary};
+use seq_io::BaseRecord;
+use seq_io::parallel::{read_process_fasta_records, read_process_fastq_records};
+use bio::alphabets::dna;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct AsmOpt {
+    /// Assembly contigs (FASTA/FASTQ, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub contigs: PathBuf,
+    /// Reference genome input
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub reference: PathBuf,
+    /// Output prefix for PAF file
+    ///
+    #[structopt(short, long, default_value = "mapquik-asm")]
+    pub prefix: String,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+    /// Minimum number of matching k-min-mers in a block
+    ///
+    #[structopt(long, default_value = "10")]
+    pub min_seeds: usize,
+    /// Minimum block length on the contig
+    ///
+    #[structopt(long, default_value = "10000")]
+    pub min_length: usize,
+    /// Number of preceding matches considered when chaining a match
+    ///
+    #[structopt(long, default_value = "50")]
+    pub lookback: usize,
+    /// Also write blocks as a UCSC chain file (<prefix>.chain)
+    ///
+    /// Lifts reference coordinates over to the contigs.
+    #[structopt(long)]
+    pub liftover: bool,
+    /// Refine chain blocks with base-level alignment
+    ///
+    /// The reference and contig are aligned between
+    /// consecutive k-min-mer anchors of each block.
+    #[structopt(long)]
+    pub refine: bool,
+    /// Longest region aligned when refining
+    ///
+    #[structopt(long, default_value = "5000")]
+    pub refine_max_len: usize,
+}
+
+// A colinear block between a contig and a reference.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub r_idx: usize, // Reference ID
+    pub rc: bool, // Strand direction
+    pub q_start: usize, // Contig start location (0-based, half-open)
+    pub q_end: usize, // Contig end location
+    pub r_start: usize, // Reference start location
+    pub r_end: usize, // Reference end location
+    pub score: usize, // Number of k-min-mer matches
+    pub identity: f64, // Identity estimated from the fraction of conserved k-min-mers
+    pub mapq: usize, // Mapping quality (lower when other blocks cover the same contig interval)
+    pub primary: bool, // False if a better block covers most of the same contig interval
+    pub matches: Vec<Match>, // Matches of the block, by contig position
+}
+
+impl Block {
+    // Reference position where the query leaves the block (at its q_end).
+    pub fn exit_pos(&self) -> usize {if self.rc {self.r_start} else {self.r_end}}
+
+    // Reference position where the query enters the block (at its q_start).
+    pub fn entry_pos(&self) -> usize {if self.rc {self.r_end} else {self.r_start}}
+}
+
+// Estimate the identity of a block from the fraction f of the contig's k-min-mers in the block that match the reference:
+// a k-min-mer spanning n bases is conserved with probability ~identity^n, hence identity ~ f^(1/n) (as in Mash).
+pub fn estimate_identity(kminmers: &[(usize, usize)], q_start: usize, q_end: usize, score: usize) -> f64 {
+    let lo = kminmers.partition_point(|k| k.0 < q_start);
+    let in_block = kminmers[lo..].iter().take_while(|k| k.0 < q_end).filter(|k| k.1 <= q_end).collect::<Vec<_>>();
+    if in_block.is_empty() || score == 0 {return 0.0;}
+    let f = (score as f64 / in_block.len() as f64).min(1.0);
+    let avg_span = in_block.iter().map(|k| (k.1 - k.0) as f64).sum::<f64>() / in_block.len() as f64;
+    f.powf(1.0 / avg_span.max(1.0))
+}
+
+// Find all colinear blocks of a contig on all references, with identity estimates, MAPQs and primary flags.
+pub fn find_blocks(q_id: &str, q_str: &[u8], params: &Params, index: &ReadOnlyIndex, lookback: usize, min_seeds: usize, min_length: usize) -> Vec<Block> {
+    let mut stats = Stats::new(q_id, q_str.len(), false);
+    let mut kminmers_it = mers::extract(q_id, q_str, params);
+    let matches_per_ref = mers::chain_matches(q_id, &mut kminmers_it, index, &mut stats);
+    let kminmers = match mers::extract(q_id, q_str, params) {
+        Some(it) => it.map(|k| (k.start, k.end)).collect::<Vec<(usize, usize)>>(),
+        None => Vec::new(),
+    };
+    let mut blocks = Vec::new();
+    for (r_idx, matches) in matches_per_ref.iter() {
+        for c in Chain::new(matches).colinear_blocks(params.g, lookback, min_seeds) {
+            let (rc, q_start, q_end, r_start, r_end) = c.span();
+            if q_end - q_start < min_length {continue;}
+            let score = c.get_count();
+            let identity = estimate_identity(&kminmers, q_start, q_end, score);
+            let matches = (0..c.len()).map(|i| c.nth(i).clone()).collect();
+            blocks.push(Block {r_idx: *r_idx, rc, q_start, q_end, r_start, r_end, score, identity, mapq: 60, primary: true, matches});
+        }
+    }
+    blocks.sort_unstable_by(|a, b| a.q_start.cmp(&b.q_start).then(b.score.cmp(&a.score)));
+    // a block is secondary if a better block covers most (> 50%) of its contig interval; its MAPQ decreases with the best competing score.
+    // Blocks are sorted by start, so each block is only compared with the following blocks that start before it ends (ties go to the earlier block).
+    let mut best_other = vec![0; blocks.len()];
+    for i in 0..blocks.len() {
+        for j in i + 1..blocks.len() {
+            if blocks[j].q_start >= blocks[i].q_end {break;}
+            let overlap = blocks[i].q_end.min(blocks[j].q_end) - blocks[j].q_start;
+            let shorter = (blocks[i].q_end - blocks[i].q_start).min(blocks[j].q_end - blocks[j].q_start);
+            if overlap * 2 <= shorter {continue;}
+            best_other[i] = best_other[i].max(blocks[j].score);
+            best_other[j] = best_other[j].max(blocks[i].score);
+            if blocks[j].score > blocks[i].score {blocks[i].primary = false;} else {blocks[j].primary = false;}
+        }
+    }
+    for (b, &best) in blocks.iter_mut().zip(best_other.iter()) {
+        if best > 0 {
+            b.mapq = if best >= b.score {0} else {(60.0 * (1.0 - best as f64 / b.score as f64)).round() as usize};
+        }
+    }
+    blocks
+}
+
+// Format a block as a PAF line (matching bases are estimated from the identity; dv:f is the estimated divergence).
+pub fn paf_line(q_id: &str, q_len: usize, b: &Block, rinfo: &RefInfo) -> String {
+    let block_len = (b.q_end - b.q_start).max(b.r_end - b.r_start);
+    let nb_match = (b.identity * (b.q_end - b.q_start) as f64).round() as usize;
+    format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\ttp:A:{}\tcm:i:{}\ts1:i:{}\tdv:f:{:.4}",
+            q_id, q_len, b.q_start, b.q_end, if b.rc {"-"} else {"+"}, rinfo.name, rinfo.len, b.r_start, b.r_end, nb_match, block_len, b.mapq,
+            if b.primary {"P"} else {"S"}, b.matches.len(), b.score, 1.0 - b.identity)
+}
+
+// Run the asm subcommand.
+pub fn run(opt: &AsmOpt) {
+    let params = opt.seeding.to_params("asm5");
+    let threads = opt.seeding.preset.threads;
+    let mut summary = Summary::new();
+    let (index, ref_map) = closures::build_index(&opt.reference, &params, threads, threads, is_fasta_filename(&opt.reference), &Mask::new(), &HashSet::new(), &mut summary);
+    let mut ref_seqs = HashMap::<String, Vec<u8>>::new(); // reference sequences by name, only needed for base-level refinement
+    if opt.liftover && opt.refine {
+        closures::for_each_record(&opt.reference, is_fasta_filename(&opt.reference), |id, _desc, seq| {ref_seqs.insert(id.to_string(), seq.to_vec());});
+    }
+    let paf_filename = format!("{}.paf", opt.prefix);
+    let mut paf_file = match File::create(&paf_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", paf_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let chain_filename = format!("{}.chain", opt.prefix);
+    let mut chain_file = if opt.liftover {
+        match File::create(&chain_filename) {
+            Err(why) => panic!("Couldn't create {}: {}", chain_filename, why),
+            Ok(f) => Some(BufWriter::new(f)),
+        }
+    } else {None};
+    let process_contig = |q_str: &[u8], q_id: &str| -> (Vec<String>, Vec<(usize, bool, Vec<Segment>)>) {
+        let blocks = find_blocks(q_id, q_str, &params, &index, opt.lookback, opt.min_seeds, opt.min_length);
+        let lines = blocks.iter().map(|b| paf_line(q_id, q_str.len(), b, &ref_map.get(&b.r_idx).unwrap())).collect();
+        let mut chains = Vec::new();
+        if opt.liftover {
+            let q_rc = if opt.refine {dna::revcomp(q_str)} else {Vec::new()};
+            for b in blocks.iter() {
+                let segments = if opt.refine {
+                    let anchors = liftover::anchors(b, q_str, &params, &index);
+                    liftover::refined_segments(&anchors, &ref_seqs[&ref_map.get(&b.r_idx).unwrap().name], if b.rc {&q_rc} else {q_str}, opt.refine_max_len)
+                } else {liftover::match_segments(b, q_str.len())};
+                if !segments.is_empty() {chains.push((b.r_idx, b.rc, segments));}
+            }
+        }
+        (lines, chains)
+    };
+    let (mut nb_contigs, mut nb_blocks, mut nb_chains) = (0, 0, 0);
+    let mut write_lines = |q_id: &str, q_len: usize, found: &mut (Vec<String>, Vec<(usize, bool, Vec<Segment>)>)| {
+        for l in found.0.iter() {writeln!(paf_file, "{}", l).expect("Error writing line.");}
+        if let Some(f) = chain_file.as_mut() {
+            for (r_idx, rc, segments) in found.1.iter() {
+                nb_chains += 1;
+                let rinfo = ref_map.get(r_idx).unwrap();
+                write!(f, "{}", liftover::chain_record(nb_chains, &rinfo.name, rinfo.len, q_id, q_len, *rc, segments)).expect("Error writing chain.");
+            }
+        }
+        nb_contigs += 1;
+        nb_blocks += found.0.len();
+        None::<()>
+    };
+    let start = Instant::now();
+    let (buf, _) = get_reader(&opt.contigs);
+    if is_fasta_filename(&opt.contigs) {
+        let reader = seq_io::fasta::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fasta_records(reader, threads as u32, threads, |record, found| {*found = process_contig(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |record, found| {write_lines(record.id().unwrap(), record.seq().len(), found)}).ok();
+    }
+    else {
+        let reader = seq_io::fastq::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fastq_records(reader, threads as u32, threads, |record, found| {*found = process_contig(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |record, found| {write_lines(record.id().unwrap(), record.seq().len(), found)}).ok();
+    }
+    println!("Mapped {} contigs as {} blocks in {:?}.", nb_contigs, nb_blocks, start.elapsed());
+    println!("Wrote blocks to {}.", paf_filename);
+    if opt.liftover {println!("Wrote {} chains to {}.", nb_chains, chain_filename);}
+}
+
@@ -0,0 +1,202 @@
+// asm_qc.rs
+// Contains the "asm-qc" subcommand, which maps reads to an assembly and reports suspected misassemblies: positions where many reads' chains break
+// (clipped ends, or segments of a read placed apart on the assembly) and regions where coverage drops.
+
+use crate::{asm::{self, Block}, closures, get_reader, is_fasta_filename, mask::Mask, presets::SeedingOpt, summary::Summary};
+use seq_io::BaseRecord;
+use seq_io::parallel::{read_process_fasta_records, read_process_fastq_records};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+const LOOKBACK: usize = 50; // preceding matches considered when chaining a match into a read segment
+
+#[derive(Debug, StructOpt)]
+pub struct AsmQcOpt {
+    /// Assembly to evaluate (FASTA, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub assembly: PathBuf,
+    /// Reads (FASTA/FASTQ, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub reads: PathBuf,
+    /// Output prefix for BED file
+    ///
+    #[structopt(short, long, default_value = "mapquik-asm-qc")]
+    pub prefix: String,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+    /// Minimum number of matching k-min-mers in a read segment
+    ///
+    #[structopt(long, default_value = "3")]
+    pub min_seeds: usize,
+    /// Minimum read segment length
+    ///
+    #[structopt(long, default_value = "1000")]
+    pub min_length: usize,
+    /// Minimum unaligned read end length to count a read as clipped
+    ///
+    #[structopt(long, default_value = "1000")]
+    pub min_clip: usize,
+    /// Maximum difference between read and assembly distances of consecutive segments
+    ///
+    /// Consecutive segments of a read further apart on the
+    /// assembly (or on another contig or strand) are split.
+    #[structopt(long, default_value = "1000")]
+    pub max_jump: usize,
+    /// Maximum distance between breakpoints of a cluster
+    ///
+    #[structopt(long, default_value = "500")]
+    pub cluster_distance: usize,
+    /// Minimum number of reads supporting a breakpoint
+    ///
+    #[structopt(long, default_value = "3")]
+    pub min_support: usize,
+    /// Window size for coverage
+    ///
+    #[structopt(short, long, default_value = "1000")]
+    pub window: usize,
+    /// Coverage drop threshold, as a fraction of the median coverage
+    ///
+    #[structopt(long, default_value = "0.25")]
+    pub min_coverage: f64,
+}
+
+// Kind of evidence of a break in a read's mapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Break {
+    Clip, // unaligned read end, away from the contig ends
+    Split, // consecutive segments of the read that are not colinear on the assembly
+}
+
+// Breakpoints of a read from its primary segments (contig ID, position, kind).
+pub fn read_breaks(q_len: usize, segments: &[Block], contig_lens: &HashMap<usize, usize>, min_clip: usize, max_jump: usize) -> Vec<(usize, usize, Break)> {
+    let mut breaks = Vec::new();
+    let (first, last) = match (segments.first(), segments.last()) {
+        (Some(f), Some(l)) => (f, l),
+        _ => return breaks,
+    };
+    // a clipped end is only evidence if the contig continues past the segment where the unaligned part of the read would be
+    let room_before = |b: &Block| if b.rc {contig_lens[&b.r_idx] - b.r_end} else {b.r_start};
+    let room_after = |b: &Block| if b.rc {b.r_start} else {contig_lens[&b.r_idx] - b.r_end};
+    if first.q_start > min_clip && room_before(first) > min_clip {breaks.push((first.r_idx, first.entry_pos(), Break::Clip));}
+    if q_len - last.q_end > min_clip && room_after(last) > min_clip {breaks.push((last.r_idx, last.exit_pos(), Break::Clip));}
+    for w in segments.windows(2) {
+        let (a, b) = (&w[0], &w[1]);
+        let q_dist = b.q_start as isize - a.q_end as isize;
+        let r_dist = if a.rc {a.exit_pos() as isize - b.entry_pos() as isize} else {b.entry_pos() as isize - a.exit_pos() as isize};
+        if a.r_idx == b.r_idx && a.rc == b.rc && (r_dist - q_dist).unsigned_abs() <= max_jump {continue;}
+        breaks.push((a.r_idx, a.exit_pos(), Break::Split));
+        breaks.push((b.r_idx, b.entry_pos(), Break::Split));
+    }
+    breaks
+}
+
+// Cluster breakpoints of a contig (position, read index, kind) within max_dist of each other.
+// Returns (start, end, nb_reads, nb_clipped, nb_split) per cluster, counting each read once.
+pub fn cluster_breaks(breaks: &mut [(usize, usize, Break)], max_dist: usize) -> Vec<(usize, usize, usize, usize, usize)> {
+    breaks.sort_unstable_by_key(|b| (b.0, b.1));
+    let mut clusters = Vec::new();
+    let mut i = 0;
+    while i < breaks.len() {
+        let mut j = i + 1;
+        while j < breaks.len() && breaks[j].0 - breaks[j - 1].0 <= max_dist {j += 1;}
+        let cluster = &breaks[i..j];
+        let reads = cluster.iter().map(|b| b.1).collect::<HashSet<usize>>();
+        let clipped = cluster.iter().filter(|b| b.2 == Break::Clip).map(|b| b.1).collect::<HashSet<usize>>();
+        let split = cluster.iter().filter(|b| b.2 == Break::Split).map(|b| b.1).collect::<HashSet<usize>>();
+        clusters.push((cluster[0].0, cluster[j - i - 1].0 + 1, reads.len(), clipped.len(), split.len()));
+        i = j;
+    }
+    clusters
+}
+
+// Run the asm-qc subcommand.
+pub fn run(opt: &AsmQcOpt) {
+    let params = opt.seeding.to_params("hifi");
+    let threads = opt.seeding.preset.threads;
+    let mut summary = Summary::new();
+    let (index, ref_map) = closures::build_index(&opt.assembly, &params, threads, threads, is_fasta_filename(&opt.assembly), &Mask::new(), &HashSet::new(), &mut summary);
+    let contig_lens = ref_map.iter().map(|r| (*r.key(), r.len)).collect::<HashMap<usize, usize>>();
+
+    // Segments and breakpoints of each read
+    let map_read = |q_str: &[u8], q_id: &str| -> (Vec<(usize, usize, usize)>, Vec<(usize, usize, Break)>) {
+        let mut segments = asm::find_blocks(q_id, q_str, &params, &index, LOOKBACK, opt.min_seeds, opt.min_length);
+        segments.retain(|b| b.primary);
+        segments.sort_unstable_by_key(|b| b.q_start);
+        let spans = segments.iter().map(|b| (b.r_idx, b.r_start, b.r_end)).collect();
+        (spans, read_breaks(q_str.len(), &segments, &contig_lens, opt.min_clip, opt.max_jump))
+    };
+    let mut coverage = contig_lens.iter().map(|(r, len)| (*r, vec![0usize; (len + opt.window - 1) / opt.window])).collect::<HashMap<usize, Vec<usize>>>();
+    let mut breaks = HashMap::<usize, Vec<(usize, usize, Break)>>::new(); // per contig: position, read index, kind
+    let (mut nb_reads, mut nb_split) = (0, 0);
+    let mut add_read = |found: &mut (Vec<(usize, usize, usize)>, Vec<(usize, usize, Break)>)| {
+        for (r_idx, r_start, r_end) in found.0.iter() {
+            let cov = coverage.get_mut(r_idx).unwrap();
+            for w in (r_start / opt.window)..=((r_end - 1) / opt.window).min(cov.len() - 1) {cov[w] += 1;}
+        }
+        if !found.1.is_empty() {nb_split += 1;}
+        for (r_idx, pos, kind) in found.1.iter() {breaks.entry(*r_idx).or_insert_with(Vec::new).push((*pos, nb_reads, *kind));}
+        nb_reads += 1;
+        None::<()>
+    };
+    let start = Instant::now();
+    let (buf, _) = get_reader(&opt.reads);
+    if is_fasta_filename(&opt.reads) {
+        let reader = seq_io::fasta::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fasta_records(reader, threads as u32, params.q, |record, found| {*found = map_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, found| {add_read(found)}).ok();
+    }
+    else {
+        let reader = seq_io::fastq::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fastq_records(reader, threads as u32, params.q, |record, found| {*found = map_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, found| {add_read(found)}).ok();
+    }
+    println!("Mapped {} reads in {:?}, {} with clipped or split mappings.", nb_reads, start.elapsed(), nb_split);
+
+    // Median coverage of covered windows, over the whole assembly
+    let mut covered = coverage.values().flatten().filter(|c| **c > 0).cloned().collect::<Vec<usize>>();
+    covered.sort_unstable();
+    let median = if covered.is_empty() {0} else {covered[covered.len() / 2]};
+    let min_cov = opt.min_coverage * median as f64;
+
+    // Breakpoint clusters and coverage drops, per contig by name
+    let mut contigs = ref_map.iter().map(|r| (r.name.clone(), *r.key())).collect::<Vec<(String, usize)>>();
+    contigs.sort_unstable();
+    let bed_filename = format!("{}.misassemblies.bed", opt.prefix);
+    let mut bed_file = match File::create(&bed_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", bed_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    writeln!(bed_file, "#contig\tstart\tend\ttype\tsupport\tdetails").expect("Error writing line.");
+    let (mut nb_breakpoints, mut nb_drops) = (0, 0);
+    for (name, r_idx) in contigs.iter() {
+        let mut records = Vec::<(usize, usize, String)>::new();
+        if let Some(b) = breaks.get_mut(r_idx) {
+            for (s, e, nb, clipped, split) in cluster_breaks(b, opt.cluster_distance) {
+                if nb < opt.min_support {continue;}
+                records.push((s, e, format!("breakpoint\t{}\tclipped={};split={}", nb, clipped, split)));
+                nb_breakpoints += 1;
+            }
+        }
+        // windows below the coverage threshold (contig end windows are expected to be less covered)
+        let cov = &coverage[r_idx];
+        let len = contig_lens[r_idx];
+        let mut w = 1;
+        while median > 0 && w + 1 < cov.len() {
+            if (cov[w] as f64) >= min_cov {w += 1; continue;}
+            let mut end = w;
+            while end + 2 < cov.len() && (cov[end + 1] as f64) < min_cov {end += 1;}
+            let lowest = cov[w..=end].iter().min().unwrap();
+            records.push((w * opt.window, ((end + 1) * opt.window).min(len), format!("coverage_drop\t{}\tmin_coverage={};median_coverage={}", lowest, lowest, median)));
+            nb_drops += 1;
+            w = end + 1;
+        }
+        records.sort_unstable_by_key(|r| (r.0, r.1));
+        for (s, e, desc) in records.iter() {writeln!(bed_file, "{}\t{}\t{}\t{}", name, s, e, desc).expect("Error writing line.");}
+    }
+    println!("Found {} breakpoints supported by at least {} reads and {} coverage drops (median coverage {}).", nb_breakpoints, opt.min_support, nb_drops, median);
+    println!("Wrote suspected misassemblies to {}.", bed_filename);
+}
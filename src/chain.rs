@@ -4,15 +4,39 @@
 use crate::{r#match::Match, Params, PseudoChainCoords};
 use std::fmt;
 
+// A large indel chained over (see Chain::link_large_indels).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Indel {
+    pub kind: char, // 'D' for a deletion from the query, 'I' for an insertion
+    pub len: usize, // Indel length
+    pub q_pos: usize, // Query position where the gap containing the indel starts
+    pub r_pos: usize, // Reference position where the gap containing the indel starts
+}
+
+// An inverted segment within a Chain: a block of opposite-strand Matches between two consecutive Matches of the Chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Inversion {
+    pub q_start: usize, // Query start location of the inverted block
+    pub q_end: usize, // Query end location
+    pub r_start: usize, // Reference start location
+    pub r_end: usize, // Reference end location
+    pub count: usize, // Number of k-min-mer matches in the inverted block
+    pub left_bp: (usize, usize), // Reference interval containing the left breakpoint
+    pub right_bp: (usize, usize), // Reference interval containing the right breakpoint
+}
+
+const MIN_INVERSION_COUNT: usize = 2; // minimum number of k-min-mer matches of an inverted block
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Chain {
     matches: Vec<Match>,
+    pub indels: Vec<Indel>, // large indels within the Chain (see link_large_indels)
 }
 impl Chain {
 
     // New Chain from &Vec of matches.
     pub fn new(matches: &[Match]) -> Self {
-        Chain {matches: matches.to_vec()}
+        Chain {matches: matches.to_vec(), indels: Vec::new()}
     }
 
     // Get total number of k-min-mer matches in the Chain (a Match can have multiple consecutive k-min-mer matches).
@@ -40,6 +64,11 @@ impl Chain {
         &self.matches[i]
     }
 
+    // Check if a Match is in the Chain.
+    pub fn contains(&self, h: &Match) -> bool {
+        self.matches.contains(h)
+    }
+
     pub fn check_match_compatible(&self, h1: &Match, h2: &Match, g: usize) -> bool {
         if h1 == h2 {return true;}
         if h1.rc != h2.rc {return false;}
@@ -149,14 +178,22 @@ impl Chain {
         if len > 1 {
             //self.retain_unique();
             //self.filter_matches_c(params.g, len - 1);
-            self.filter_matches_max(params.g);
+            if params.max_indel > 0 || params.splice {
+                // the Matches left out of the chain are candidates for linking across large indels
+                let all = self.matches.clone();
+                self.filter_matches_max(params.g);
+                self.link_large_indels(&all, params);
+            }
+            else {self.filter_matches_max(params.g);}
             //self.check_colinear(params.g);
         }
         let len_f = self.len();
         if len_f == 0 {return None;}
-        let score = self.get_count(); 
+        // large indels chained over cost score, and lower MAPQ in proportion
+        let count = self.get_count();
+        let score = count.saturating_sub(self.indels.iter().map(|i| Chain::indel_cost(i, params)).sum::<usize>());
         let mapq = match (params.s != 0 && params.c != 0) && ((len_f >= params.c) || (score >= params.s)) {
-            true => 60,
+            true => 60 * score / count,
             false => 0,
         };
         let first = self.first();
@@ -169,6 +206,157 @@ impl Chain {
     }
     
 
+    // Gap length difference (reference minus query) between Matches u and v, u being before v on the query.
+    fn gap_diff(u: &Match, v: &Match) -> isize {
+        let g_q = v.q_start as isize - u.q_end as isize;
+        let g_r = if u.rc {u.r_start as isize - v.r_end as isize} else {v.r_start as isize - u.r_end as isize};
+        g_r - g_q
+    }
+
+    // Score cost of a large indel chained over, in k-min-mer matches: the indel penalty plus log2 of its length (introns are free in spliced mode).
+    fn indel_cost(indel: &Indel, params: &Params) -> usize {
+        if params.splice && indel.kind == 'D' && indel.len >= params.min_intron {return 0;}
+        params.indel_penalty + (indel.len as f64).log2() as usize
+    }
+
+    // Extend the Chain across large indels: blocks of the other Matches (colinear within g) that lie entirely before or after the Chain,
+    // in the same order on the query and the reference, with a gap length difference above g but at most max_indel (max_intron for deletions in spliced mode), are added one at a time
+    // (the block with the largest gain, i.e. k-min-mer matches minus indel cost, first), as long as the gain is positive. The indels are recorded.
+    pub fn link_large_indels(&mut self, all: &[Match], params: &Params) {
+        if self.matches.is_empty() {return;}
+        let rc = self.first().rc;
+        // in spliced mode, introns are deletions of up to max_intron bases
+        let max_deletion = if params.splice {params.max_intron.max(params.max_indel)} else {params.max_indel};
+        let rest = all.iter().filter(|h| h.rc == rc && !self.contains(h)).cloned().collect::<Vec<Match>>();
+        if rest.is_empty() {return;}
+        let mut blocks = Chain::new(&rest).colinear_blocks(params.g, rest.len(), params.indel_penalty + 1);
+        self.matches.sort_unstable_by_key(|h| h.q_start);
+        loop {
+            let (first, last) = (self.first().clone(), self.last().clone());
+            let mut best : Option<(usize, isize, Indel)> = None;
+            for (i, b) in blocks.iter().enumerate() {
+                let (b_first, b_last) = (b.first(), b.last());
+                // the block follows the Chain (u, v) or precedes it (v, u) on both sequences
+                let (u, v) = if b_first.q_start >= last.q_end {(&last, b_first)} else if b_last.q_end <= first.q_start {(b_last, &first)} else {continue};
+                let on_ref = if rc {v.r_end <= u.r_start} else {v.r_start >= u.r_end};
+                let diff = Chain::gap_diff(u, v);
+                let max_diff = if diff > 0 {max_deletion} else {params.max_indel};
+                if !on_ref || diff.unsigned_abs() <= params.g || diff.unsigned_abs() > max_diff {continue;}
+                let r_pos = if rc {u.r_start} else {u.r_end};
+                let indel = Indel {kind: if diff > 0 {'D'} else {'I'}, len: diff.unsigned_abs(), q_pos: u.q_end, r_pos};
+                let gain = b.get_count() as isize - Chain::indel_cost(&indel, params) as isize;
+                if gain > 0 && best.as_ref().map_or(true, |(_, g, _)| gain > *g) {best = Some((i, gain, indel));}
+            }
+            match best {
+                Some((i, _, indel)) => {
+                    let b = blocks.swap_remove(i);
+                    self.matches.extend(b.matches);
+                    self.matches.sort_unstable_by_key(|h| h.q_start);
+                    self.indels.push(indel);
+                }
+                None => break,
+            }
+        }
+        self.indels.sort_unstable_by_key(|i| i.q_pos);
+    }
+
+    // Find inverted segments (forward-reverse-forward pattern): for each pair of consecutive Matches of the Chain, the best block of
+    // opposite-strand Matches (colinear within g) lying between them on both the query and the reference.
+    pub fn find_inversions(&self, all: &[Match], params: &Params) -> Vec<Inversion> {
+        let mut inversions = Vec::new();
+        if self.len() < 2 {return inversions;}
+        let rc = self.first().rc;
+        let opposite = all.iter().filter(|h| h.rc != rc).cloned().collect::<Vec<Match>>();
+        if opposite.is_empty() {return inversions;}
+        let blocks = Chain::new(&opposite).colinear_blocks(params.g, opposite.len(), MIN_INVERSION_COUNT);
+        let mut chain = self.matches.iter().collect::<Vec<&Match>>();
+        chain.sort_unstable_by_key(|h| h.q_start);
+        for w in chain.windows(2) {
+            let (u, v) = (w[0], w[1]);
+            // reference interval between the two Matches
+            let (r_lo, r_hi) = if rc {(v.r_end, u.r_start)} else {(u.r_end, v.r_start)};
+            let mut best : Option<Inversion> = None;
+            for b in blocks.iter() {
+                let (_, q_start, q_end, r_start, r_end) = b.span();
+                if q_start < u.q_end || q_end > v.q_start || r_start < r_lo || r_end > r_hi {continue;}
+                let count = b.get_count();
+                if best.as_ref().map_or(true, |i| count > i.count) {
+                    best = Some(Inversion {q_start, q_end, r_start, r_end, count, left_bp: (r_lo, r_start), right_bp: (r_end, r_hi)});
+                }
+            }
+            if let Some(i) = best {inversions.push(i);}
+        }
+        inversions
+    }
+
+    // Split the Chain into exons (spliced mode): query and reference spans (half-open) of runs of Matches, in query order,
+    // separated by reference-only gaps of at least min_intron bases.
+    pub fn exons(&self, min_intron: usize) -> Vec<(usize, usize, usize, usize)> {
+        let mut matches = self.matches.iter().collect::<Vec<&Match>>();
+        matches.sort_unstable_by_key(|h| h.q_start);
+        let mut exons : Vec<(usize, usize, usize, usize)> = Vec::new();
+        for (i, h) in matches.iter().enumerate() {
+            if i > 0 && Chain::gap_diff(matches[i - 1], h) < min_intron as isize {
+                let e = exons.last_mut().unwrap();
+                *e = (e.0.min(h.q_start), e.1.max(h.q_end), e.2.min(h.r_start), e.3.max(h.r_end));
+            }
+            else {exons.push((h.q_start, h.q_end, h.r_start, h.r_end));}
+        }
+        exons
+    }
+
+    // Query and reference span of the Chain (0-based, half-open), and its strand.
+    pub fn span(&self) -> (bool, usize, usize, usize, usize) {
+        let q_start = self.matches.iter().map(|h| h.q_start).min().unwrap();
+        let q_end = self.matches.iter().map(|h| h.q_end).max().unwrap();
+        let r_start = self.matches.iter().map(|h| h.r_start).min().unwrap();
+        let r_end = self.matches.iter().map(|h| h.r_end).max().unwrap();
+        (self.first().rc, q_start, q_end, r_start, r_end)
+    }
+
+    // Split the Matches into all colinear blocks (used for assemblies, where a query can have many blocks on a reference, on both strands).
+    // Blocks are found by dynamic programming over Matches sorted by query position, where each Match is chained to the best compatible
+    // Match among at most `lookback` predecessors (linear rather than quadratic in the number of Matches), then extracted from the best
+    // scoring ends, each Match being used at most once. Only blocks of at least min_score k-min-mer matches are kept.
+    pub fn colinear_blocks(&self, g: usize, lookback: usize, min_score: usize) -> Vec<Chain> {
+        let mut order = (0..self.len()).collect::<Vec<usize>>();
+        order.sort_unstable_by_key(|&i| (self.nth(i).rc, self.nth(i).q_start, self.nth(i).r_start));
+        let matches = order.iter().map(|&i| self.nth(i)).collect::<Vec<&Match>>();
+        let n = matches.len();
+        let mut scores = vec![0; n];
+        let mut preds : Vec<Option<usize>> = vec![None; n];
+        for i in 0..n {
+            scores[i] = matches[i].count;
+            for j in (i.saturating_sub(lookback)..i).rev() {
+                let (u, v) = (matches[j], matches[i]);
+                if u.rc != v.rc || u.q_start == v.q_start {continue;}
+                if scores[j] + v.count > scores[i] && self.check_match_compatible(u, v, g) {
+                    scores[i] = scores[j] + v.count;
+                    preds[i] = Some(j);
+                }
+            }
+        }
+        let mut ends = (0..n).collect::<Vec<usize>>();
+        ends.sort_unstable_by(|a, b| scores[*b].cmp(&scores[*a]).then(a.cmp(b)));
+        let mut used = vec![false; n];
+        let mut blocks = Vec::new();
+        for end in ends {
+            if used[end] {continue;}
+            let mut block = Vec::new();
+            let mut cur = Some(end);
+            while let Some(i) = cur {
+                if used[i] {break;}
+                used[i] = true;
+                block.push(matches[i].clone());
+                cur = preds[i];
+            }
+            if block.iter().map(|h| h.count).sum::<usize>() < min_score {continue;}
+            block.reverse();
+            blocks.push(Chain {matches: block, indels: Vec::new()});
+        }
+        blocks
+    }
+
     // Obtains query and reference intervals that are not covered by a Match in the final Chain object (only for base-level alignment).
     /*pub fn get_remaining_seqs(&self, m: &Match) -> (Vec<(usize, usize)>, Vec<(usize, usize)>) {
         let mut q_coords = Vec::<(usize, usize)>::new();
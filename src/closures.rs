@@ -4,71 +4,94 @@
 use std::error::Error;
 use std::io::{Write, BufWriter};
 use std::fs::{File};
+use std::collections::HashSet;
 use seq_io::BaseRecord;
 use seq_io::parallel::{read_process_fasta_records, read_process_fastq_records, read_process_fastx_records};
 use dashmap::DashMap;
 use super::mers;
 use std::path::{Path, PathBuf};
 use super::Params;
-use crate::get_reader;
+use crate::{get_reader, is_gfa_filename};
 use std::time::Instant;
-use crate::index::{Index, ReadOnlyIndex};
+use crate::index::{Index, ReadOnlyIndex, RefInfo};
+use crate::mask::Mask;
+use crate::stats::{Stats, StatsWriter, Status};
+use crate::summary::Summary;
+use crate::coverage::Coverage;
+use crate::{dotplot, report};
+use crate::splice::Splice;
+use crate::gfa::{self, Graph};
 //use crate::align::{get_slices, align_slices, AlignStats};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use rust_parallelfastx::parallel_fastx;
 use std::sync::mpsc;
 
-// Main function for all FASTA parsing + mapping / alignment functions.
-pub fn run_mers(filename: &PathBuf, ref_filename: &PathBuf, params: &Params, ref_threads: usize, threads: usize, ref_queue_len: usize, queue_len: usize, fasta_reads: bool, ref_fasta_reads: bool, output_prefix: &Path) {
+// Sequentially parse a FASTA/FASTQ file (raw or compressed), calling f on the ID, description and uppercase sequence of each record.
+pub fn for_each_record<F: FnMut(&str, Option<&str>, &[u8])>(filename: &PathBuf, fasta: bool, mut f: F) {
+    let (buf, _) = get_reader(filename);
+    if fasta {
+        for record in bio::io::fasta::Reader::new(buf).records() {
+            let record = record.expect("Error parsing FASTA record.");
+            f(record.id(), record.desc(), &record.seq().to_ascii_uppercase());
+        }
+    }
+    else {
+        for record in bio::io::fastq::Reader::new(buf).records() {
+            let record = record.expect("Error parsing FASTQ record.");
+            f(record.id(), record.desc(), &record.seq().to_ascii_uppercase());
+        }
+    }
+}
 
-    let mers_index = Index::new(); // Index of reference k-min-mer entries
-    //let mut aln_coords : Arc<DashMap<String, Vec<AlignCand>>> =  Arc::new(DashMap::new()); // Index of AlignCand objects (see mers.rs for a definition) per reference
-    //let mut aln_coords_q : Arc<DashMap<String, Vec<Offset>>> =  Arc::new(DashMap::new()); // Index of intervals that need to be aligned per query
-    //let mut aln_seqs_cow : Arc<DashMap<(String, Offset), Cow<[u8]>>> =  Arc::new(DashMap::new()); // Index of pointers to string slices that need to be aligned per reference
-    let ref_i = AtomicUsize::new(0);
-    let ref_map : DashMap<usize, (String, usize)> = DashMap::new(); // Sequence lengths per reference
+// Check if a FASTA header description marks the sequence as circular (e.g. "circular=true", "[topology=circular]", or a bare "circular" word).
+pub fn is_circular_header(desc: &str) -> bool {
+    desc.split(|c: char| c.is_whitespace() || c == '[' || c == ']' || c == ';' || c == ',').any(|tag| {
+        let tag = tag.to_ascii_lowercase();
+        tag == "circular" || tag == "circular=true" || tag == "circular=yes" || tag == "topology=circular" || tag == "completeness=complete_circular"
+    })
+}
 
-    // PAF file generation
-    let paf_filename = format!("{}{}", output_prefix.to_str().unwrap(), ".paf");
-    let mut paf_file = match File::create(&paf_filename) {
-        Err(why) => panic!("Couldn't create {}: {}", paf_filename, why.description()),
-        Ok(paf_file) => BufWriter::new(paf_file),
-    };
+// Index the k-min-mers of all references. Returns the read-only Index and the RefInfo of each reference, and records index counts and time in the Summary.
+pub fn build_index(ref_filename: &PathBuf, params: &Params, ref_threads: usize, ref_queue_len: usize, ref_fasta_reads: bool, mask: &Mask, circular_names: &HashSet<String>, summary: &mut Summary) -> (ReadOnlyIndex, DashMap<usize, RefInfo>) {
 
-    // Unmapped read file generation
-    /*let unmap_path = format!("{}{}", output_prefix.to_str().unwrap(), ".unmapped.out");
-    let unmap_file = match File::create(&unmap_path) {
-        Err(why) => panic!("Couldn't create {}: {}", unmap_path, why.to_string()),
-        Ok(unmap_file) => unmap_file,
-    };*/
+    let mers_index = Index::new(); // Index of reference k-min-mer entries
+    let ref_i = AtomicUsize::new(0);
+    let ref_map : DashMap<usize, RefInfo> = DashMap::new(); // Sequence names, lengths and flags per reference
 
     // Closure for indexing reference k-min-mers
-    let index_mers = |seq_id: &str, seq: &[u8], params: &Params| -> usize {
+    let index_mers = |seq_id: &str, seq: &[u8], params: &Params, circular: bool| -> (usize, usize) {
         let ref_idx = ref_i.fetch_add(1, Ordering::Relaxed);
-        let nb_mers = mers::ref_extract(ref_idx, seq, params, &mers_index);
-        ref_map.insert(ref_idx, (seq_id.to_string(), seq.len()));
+        let hap = mask.haplotype(seq_id);
+        let nb_mers = mers::ref_extract(ref_idx, seq, params, &mers_index, mask.get_regions(seq_id), circular, hap);
+        ref_map.insert(ref_idx, RefInfo::new(seq_id, seq.len(), mask.is_decoy(seq_id), circular, hap));
         nb_mers
     };
 
     // Closures for obtaining k-min-mers from references
 
-    let ref_process_read_aux_mer = |ref_str: &[u8], ref_id: &str| -> Option<u64> {
-        let nb_mers = index_mers(ref_id, ref_str, params);
+    let ref_process_read_aux_mer = |ref_str: &[u8], ref_id: &str, ref_desc: Option<&str>| -> Option<u64> {
+        let circular = circular_names.contains(ref_id) || ref_desc.map_or(false, is_circular_header);
+        let (nb_mers, nb_masked) = index_mers(ref_id, ref_str, params, circular);
         //if params.a {aln_coords.insert(ref_id.to_string(), Vec::new());}
-        println!("Indexed reference {}: {} k-min-mers.", ref_id, nb_mers);
+        let circular_s = if circular {", circular"} else {""};
+        if nb_masked > 0 {println!("Indexed reference {}: {} k-min-mers ({} masked{}).", ref_id, nb_mers, nb_masked, circular_s);}
+        else if circular {println!("Indexed reference {}: {} k-min-mers (circular).", ref_id, nb_mers);}
+        else {println!("Indexed reference {}: {} k-min-mers.", ref_id, nb_mers);}
         Some(1)
     };
 
     let ref_process_read_fasta_mer = |record: seq_io::fasta::RefRecord, found: &mut Option<u64>| {
         let ref_str = record.seq().to_ascii_uppercase(); 
         let ref_id = record.id().unwrap();
-        *found = ref_process_read_aux_mer(&ref_str, ref_id);
+        let ref_desc = record.desc().and_then(|d| d.ok());
+        *found = ref_process_read_aux_mer(&ref_str, ref_id, ref_desc);
 
     };
     let ref_process_read_fastq_mer = |record: seq_io::fastq::RefRecord, found: &mut Option<u64>| {
         let ref_str = record.seq().to_ascii_uppercase(); 
         let ref_id = record.id().unwrap();
-        *found = ref_process_read_aux_mer(&ref_str, ref_id);
+        let ref_desc = record.desc().and_then(|d| d.ok());
+        *found = ref_process_read_aux_mer(&ref_str, ref_id, ref_desc);
     };
     let ref_main_thread_mer = |_found: &mut Option<u64>| { // runs in main thread
         None::<()>
@@ -89,9 +112,49 @@ pub fn run_mers(filename: &PathBuf, ref_filename: &PathBuf, params: &Params, ref
         read_process_fastq_records(reader, ref_threads as u32, ref_queue_len, ref_process_read_fastq_mer, |_record, found| {ref_main_thread_mer(found)}).ok();
     }
     let duration = start.elapsed();
-    println!("Indexed {} unique k-min-mers in {:?}.", mers_index.get_count(), duration);
+    let (nb_unique, nb_repeated) = mers_index.get_counts();
+    println!("Indexed {} unique k-min-mers in {:?}.", nb_unique, duration);
+    summary.nb_unique = nb_unique;
+    summary.nb_repeated = nb_repeated;
+    summary.index_duration = duration;
+
+    (ReadOnlyIndex::new(mers_index), ref_map)
+}
+
+// Main function for all FASTA parsing + mapping / alignment functions. Returns the run-level statistics.
+pub fn run_mers(filename: &PathBuf, ref_filename: &PathBuf, params: &Params, ref_threads: usize, threads: usize, ref_queue_len: usize, queue_len: usize, fasta_reads: bool, ref_fasta_reads: bool, output_prefix: &Path, mask: &Mask, circular_names: &HashSet<String>) -> Summary {
+
+    //let mut aln_coords : Arc<DashMap<String, Vec<AlignCand>>> =  Arc::new(DashMap::new()); // Index of AlignCand objects (see mers.rs for a definition) per reference
+    //let mut aln_coords_q : Arc<DashMap<String, Vec<Offset>>> =  Arc::new(DashMap::new()); // Index of intervals that need to be aligned per query
+    //let mut aln_seqs_cow : Arc<DashMap<(String, Offset), Cow<[u8]>>> =  Arc::new(DashMap::new()); // Index of pointers to string slices that need to be aligned per reference
+    let mut summary = Summary::new(); // Run-level statistics
+    let stats_writer = if params.read_stats {Some(StatsWriter::new(output_prefix.to_str().unwrap()))} else {None}; // Per-read statistics file
+
+    // A GFA reference is a pangenome graph: its paths are indexed, and mappings are written as GAF
+    let graph = if is_gfa_filename(ref_filename) {Some(Graph::load(ref_filename))} else {None};
+    if params.splice && graph.is_some() {panic!("Spliced mapping is not supported on GFA references.");}
+
+    // PAF file generation
+    let paf_filename = format!("{}{}", output_prefix.to_str().unwrap(), if graph.is_some() {".gaf"} else {".paf"});
+    let mut paf_file = match File::create(&paf_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", paf_filename, why.description()),
+        Ok(paf_file) => BufWriter::new(paf_file),
+    };
+
+    // Unmapped read file generation
+    /*let unmap_path = format!("{}{}", output_prefix.to_str().unwrap(), ".unmapped.out");
+    let unmap_file = match File::create(&unmap_path) {
+        Err(why) => panic!("Couldn't create {}: {}", unmap_path, why.to_string()),
+        Ok(unmap_file) => unmap_file,
+    };*/
 
-    let mers_index = ReadOnlyIndex::new(mers_index.index);
+    let (mers_index, ref_map) = match &graph {
+        Some((g, seqs)) => gfa::build_index(g, seqs, params, &mut summary),
+        None => build_index(ref_filename, params, ref_threads, ref_queue_len, ref_fasta_reads, mask, circular_names, &mut summary),
+    };
+    let graph = graph.map(|(g, _)| g); // segment sequences are no longer needed
+    let coverage = if params.coverage_bin > 0 {Some(Coverage::new(params.coverage_bin, params.coverage_min_mapq, &ref_map))} else {None}; // Binned coverage of reported mappings
+    let splice = if params.splice {Some(Splice::new(ref_filename, ref_fasta_reads, params.gtf.as_ref(), output_prefix.to_str().unwrap()))} else {None}; // Junction refinement and transcript models
 
     // Done, start processing queries
     
@@ -99,7 +162,17 @@ pub fn run_mers(filename: &PathBuf, ref_filename: &PathBuf, params: &Params, ref
 
     let query_process_read_aux_mer = |seq_str: &[u8], seq_id: &str| -> (String, Option<String>) {
         //if params.a {aln_coords_q.insert(seq_id.to_string(), vec![]);}
-        let match_opt = mers::find_matches(seq_id, seq_str.len(), seq_str, &ref_map, &mers_index, params); //&aln_coords);
+        let mut stats = Stats::new(seq_id, seq_str.len(), params.read_stats);
+        stats.record_anchors = params.dotplot.contains(seq_id);
+        let mut match_opt = mers::find_matches(seq_id, seq_str.len(), seq_str, &ref_map, &mers_index, params, graph.as_ref(), &mut stats); //&aln_coords);
+        if let (Some(sp), Some(l)) = (&splice, &match_opt) {
+            if !ref_map.get(&stats.r_idx).unwrap().circular {match_opt = Some(sp.process(seq_id, seq_str, &stats.exons, l));}
+        }
+        if let (Some(g), Some(l)) = (&graph, &match_opt) {match_opt = Some(g.gaf(l));}
+        if stats.record_anchors {dotplot::write_svg(output_prefix.to_str().unwrap(), seq_id, &stats, &ref_map);}
+        summary.add(&stats);
+        if let Some(cov) = &coverage {cov.add(&stats);}
+        if let Some(w) = &stats_writer {w.write(&mut stats);}
         (seq_id.to_string(), match_opt)
     };
     let query_process_read_fasta_mer = |record: seq_io::fasta::RefRecord, found: &mut (String, Option<String>)| {
@@ -209,6 +282,12 @@ pub fn run_mers(filename: &PathBuf, ref_filename: &PathBuf, params: &Params, ref
 
     let query_duration = query_start.elapsed();
     println!("Mapped query sequences in {:?}.", query_duration);
+    let nb_decoy = summary.get_unmapped(Status::Decoy);
+    if nb_decoy > 0 {println!("Suppressed {} query sequences placed on decoy references.", nb_decoy);}
+    summary.map_duration = query_duration;
+    summary.write_json(output_prefix.to_str().unwrap(), params, &ref_map);
+    report::write_html(&summary, output_prefix.to_str().unwrap(), params, &ref_map);
+    if let Some(cov) = &coverage {cov.write_bedgraph(output_prefix.to_str().unwrap(), &ref_map);}
 
 
 
@@ -254,4 +333,5 @@ pub fn run_mers(filename: &PathBuf, ref_filename: &PathBuf, params: &Params, ref
     }
     */
     //println!("current time before exiting closures {:?}",Utc::now());
+    summary
 }
@@ -0,0 +1,155 @@
+// compare.rs
+// Contains the "compare" subcommand, which measures the concordance of two sets of mappings of the same reads (native version of experiments/intersect_pafs.py).
+
+use crate::paf::{self, PafRecord};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct CompareOpt {
+    /// First set of mappings (PAF or SAM, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub a: PathBuf,
+    /// Second set of mappings (PAF or SAM, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub b: PathBuf,
+    /// Minimum overlap between two mappings of a read
+    ///
+    /// Two mappings on the same reference are concordant if
+    /// they overlap by at least this fraction of their union.
+    #[structopt(long, default_value = "0.1")]
+    pub min_overlap: f64,
+    /// Comma-separated MAPQ thresholds for the breakdown
+    ///
+    #[structopt(long, default_value = "0,1,10,30,60")]
+    pub mapq: String,
+    /// Compare all records of a read
+    ///
+    /// By default, only the primary mapping of each read is
+    /// compared. With this flag, secondary and supplementary
+    /// records are used too: a read is concordant if any
+    /// record of the first set overlaps any record of the
+    /// second.
+    #[structopt(long)]
+    pub all_records: bool,
+    /// Write read IDs of each category to <prefix>.<category>.txt
+    ///
+    /// Each line holds a read ID and its MAPQ in both sets
+    /// (0 if unmapped).
+    #[structopt(short, long)]
+    pub prefix: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Concordant,
+    DiscordantSameRef, // mapped to the same reference, at non-overlapping locations
+    DiscordantDiffRef, // mapped to different references
+    OnlyA, // mapped in the first set only
+    OnlyB, // mapped in the second set only
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [Category::Concordant, Category::DiscordantSameRef, Category::DiscordantDiffRef, Category::OnlyA, Category::OnlyB];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Concordant => "concordant",
+            Category::DiscordantSameRef => "discordant_same_ref",
+            Category::DiscordantDiffRef => "discordant_diff_ref",
+            Category::OnlyA => "only_a",
+            Category::OnlyB => "only_b",
+        }
+    }
+}
+
+// Group records by read name. Unless all records are kept, only the first primary record of each read is kept.
+pub fn group_records(records: Vec<PafRecord>, all_records: bool) -> HashMap<String, Vec<PafRecord>> {
+    let mut reads = HashMap::<String, Vec<PafRecord>>::new();
+    for rec in records.into_iter() {
+        if !all_records && !rec.primary {continue;}
+        let recs = reads.entry(rec.q_name.clone()).or_default();
+        if all_records || recs.is_empty() {recs.push(rec);}
+    }
+    reads
+}
+
+// MAPQ of a read's mappings (the highest over its records).
+fn read_mapq(recs: &[PafRecord]) -> usize {
+    recs.iter().map(|r| r.mapq).max().unwrap_or(0)
+}
+
+// Category of a read mapped in both sets.
+pub fn compare_read(recs_a: &[PafRecord], recs_b: &[PafRecord], min_overlap: f64) -> Category {
+    let mut same_ref = false;
+    for ra in recs_a.iter() {
+        for rb in recs_b.iter() {
+            if ra.t_name != rb.t_name {continue;}
+            same_ref = true;
+            if paf::overlap_ratio(ra.t_start, ra.t_end, rb.t_start, rb.t_end) >= min_overlap {return Category::Concordant;}
+        }
+    }
+    if same_ref {Category::DiscordantSameRef} else {Category::DiscordantDiffRef}
+}
+
+fn percent(a: usize, b: usize) -> f64 {
+    if b == 0 {0.0} else {a as f64 * 100.0 / b as f64}
+}
+
+// Run the compare subcommand.
+pub fn run(opt: &CompareOpt) {
+    let reads_a = group_records(paf::read_records(&opt.a), opt.all_records);
+    let reads_b = group_records(paf::read_records(&opt.b), opt.all_records);
+    let thresholds = crate::eval::parse_bins(&opt.mapq).iter().map(|q| *q as usize).collect::<Vec<usize>>();
+    // read name, category, and MAPQ in both sets (0 if unmapped)
+    let mut results = Vec::<(&String, Category, usize, usize)>::new();
+    for (name, recs_a) in reads_a.iter() {
+        match reads_b.get(name) {
+            Some(recs_b) => results.push((name, compare_read(recs_a, recs_b, opt.min_overlap), read_mapq(recs_a), read_mapq(recs_b))),
+            None => results.push((name, Category::OnlyA, read_mapq(recs_a), 0)),
+        }
+    }
+    for (name, recs_b) in reads_b.iter() {
+        if !reads_a.contains_key(name) {results.push((name, Category::OnlyB, 0, read_mapq(recs_b)));}
+    }
+    results.sort_unstable_by(|x, y| x.0.cmp(y.0));
+    let count = |c: Category| results.iter().filter(|r| r.1 == c).count();
+    let nb_both = results.len() - count(Category::OnlyA) - count(Category::OnlyB);
+    println!("Mapped reads in {}: {}", opt.a.display(), reads_a.len());
+    println!("Mapped reads in {}: {}", opt.b.display(), reads_b.len());
+    println!("Mapped in both: {}", nb_both);
+    for c in Category::ALL.iter() {
+        let n = count(*c);
+        println!("{}: {} ({:.2}% of {}, {:.2}% of {})", c.name(), n, percent(n, reads_a.len()), opt.a.display(), percent(n, reads_b.len()), opt.b.display());
+    }
+    // MAPQ-aware breakdown: a read counts at threshold q if it has MAPQ >= q in each set it is mapped in
+    println!("#min_mapq\t{}", Category::ALL.iter().map(|c| c.name()).collect::<Vec<&str>>().join("\t"));
+    for q in thresholds.iter() {
+        let counts = Category::ALL.iter().map(|c| {
+            results.iter().filter(|r| r.1 == *c && match c {
+                Category::OnlyA => r.2 >= *q,
+                Category::OnlyB => r.3 >= *q,
+                _ => r.2 >= *q && r.3 >= *q,
+            }).count().to_string()
+        }).collect::<Vec<String>>();
+        println!("{}\t{}", q, counts.join("\t"));
+    }
+    if let Some(prefix) = &opt.prefix {
+        for c in Category::ALL.iter() {
+            let path = format!("{}.{}.txt", prefix, c.name());
+            let mut f = match File::create(&path) {
+                Err(why) => panic!("Couldn't create {}: {}", path, why),
+                Ok(f) => BufWriter::new(f),
+            };
+            for r in results.iter().filter(|r| r.1 == *c) {
+                writeln!(f, "{}\t{}\t{}", r.0, r.2, r.3).expect("Error writing read IDs.");
+            }
+        }
+        println!("Wrote read IDs per category to {}.<category>.txt.", prefix);
+    }
+}
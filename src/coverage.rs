@@ -0,0 +1,71 @@
+// coverage.rs
+// Contains the "Coverage" struct, which accumulates the reference spans of reported mappings in fixed-size bins (lock-free counters), and writes them as a bedGraph.
+
+use crate::{index::RefInfo, stats::{Stats, Status}};
+use dashmap::DashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct Coverage {
+    bin_size: usize, // Bin size in bases
+    min_mapq: usize, // Mappings with a lower MAPQ are not counted
+    bins: Vec<Vec<AtomicU64>>, // Covered bases per bin, per reference
+}
+
+impl Coverage {
+
+    // New Coverage with empty bins for all references (reference IDs are consecutive from 0).
+    pub fn new(bin_size: usize, min_mapq: usize, ref_map: &DashMap<usize, RefInfo>) -> Self {
+        if bin_size == 0 {panic!("Coverage bin size must be > 0.");}
+        let mut bins : Vec<Vec<AtomicU64>> = (0..ref_map.len()).map(|_| Vec::new()).collect();
+        for r in ref_map.iter() {
+            let nb_bins = (r.len + bin_size - 1) / bin_size;
+            bins[*r.key()] = (0..nb_bins).map(|_| AtomicU64::new(0)).collect();
+        }
+        Coverage {bin_size, min_mapq, bins}
+    }
+
+    // Add the reference spans of a read's reported mapping (called from mapping threads).
+    pub fn add(&self, stats: &Stats) {
+        if stats.status != Status::Mapped || stats.mapq < self.min_mapq {return;}
+        let bins = &self.bins[stats.r_idx];
+        for &(start, end) in stats.r_spans.iter() {
+            let end = end.min(bins.len() * self.bin_size);
+            if start >= end {continue;}
+            for b in (start / self.bin_size)..=((end - 1) / self.bin_size) {
+                let bin_start = b * self.bin_size;
+                let bin_end = bin_start + self.bin_size;
+                let overlap = end.min(bin_end) - start.max(bin_start);
+                bins[b].fetch_add(overlap as u64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Write the mean depth of each bin to <prefix>.coverage.bedgraph (consecutive bins of equal depth are merged).
+    pub fn write_bedgraph(&self, output_prefix: &str, ref_map: &DashMap<usize, RefInfo>) {
+        let bedgraph_path = format!("{}{}", output_prefix, ".coverage.bedgraph");
+        let mut f = match File::create(&bedgraph_path) {
+            Err(why) => panic!("Couldn't create {}: {}", bedgraph_path, why),
+            Ok(f) => BufWriter::new(f),
+        };
+        for (r_idx, bins) in self.bins.iter().enumerate() {
+            let rinfo = ref_map.get(&r_idx).unwrap();
+            let mut prev : Option<(usize, usize, String)> = None; // start, end, depth
+            for (b, bases) in bins.iter().enumerate() {
+                let bin_start = b * self.bin_size;
+                let bin_end = (bin_start + self.bin_size).min(rinfo.len);
+                let depth = format!("{:.2}", bases.load(Ordering::Relaxed) as f64 / (bin_end - bin_start) as f64);
+                match prev.as_mut() {
+                    Some(p) if p.2 == depth => {p.1 = bin_end;}
+                    _ => {
+                        if let Some((s, e, d)) = prev.take() {writeln!(f, "{}\t{}\t{}\t{}", rinfo.name, s, e, d).expect("Error writing coverage line.");}
+                        prev = Some((bin_start, bin_end, depth));
+                    }
+                }
+            }
+            if let Some((s, e, d)) = prev {writeln!(f, "{}\t{}\t{}\t{}", rinfo.name, s, e, d).expect("Error writing coverage line.");}
+        }
+        println!("Wrote coverage to {}.", bedgraph_path);
+    }
+}
@@ -0,0 +1,93 @@
+// dotplot.rs
+// Writes an SVG dot plot of the Matches of a read (query vs. reference coordinates), one panel per candidate reference,
+// colored by strand and by whether each Match was kept by chaining.
+
+use crate::{index::RefInfo, stats::{Stats, Status}};
+use dashmap::DashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const PLOT_SIZE: f64 = 480.0; // width and height of a panel's plot area
+const MARGIN: f64 = 70.0; // space around a plot area, for axes and labels
+const MAX_PANELS: usize = 4; // references with the most matching k-min-mers are plotted
+
+// Stroke color of a Match, by strand and chaining outcome.
+fn color(rc: bool, kept: bool) -> &'static str {
+    match (rc, kept) {
+        (false, true) => "#1f77b4",
+        (true, true) => "#d62728",
+        (false, false) => "#9ecae1",
+        (true, false) => "#fcae91",
+    }
+}
+
+// Escape a string for use in SVG text.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// File name of the dot plot of a read (characters that are unsafe in file names are replaced).
+pub fn svg_path(output_prefix: &str, q_id: &str) -> String {
+    let name : String = q_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {c} else {'_'}).collect();
+    format!("{}.{}.dotplot.svg", output_prefix, name)
+}
+
+// Write the dot plot of the Matches recorded in stats to <prefix>.<read>.dotplot.svg.
+pub fn write_svg(output_prefix: &str, q_id: &str, stats: &Stats, ref_map: &DashMap<usize, RefInfo>) {
+    // references ordered by number of matching k-min-mers
+    let mut refs = Vec::<(usize, usize)>::new();
+    for (r_idx, h, _) in stats.anchors.iter() {
+        match refs.iter_mut().find(|(r, _)| r == r_idx) {
+            Some(r) => r.1 += h.count,
+            None => refs.push((*r_idx, h.count)),
+        }
+    }
+    refs.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    refs.truncate(MAX_PANELS);
+    let panel_h = PLOT_SIZE + 2.0 * MARGIN;
+    let width = PLOT_SIZE + 2.0 * MARGIN;
+    let height = 40.0 + panel_h * refs.len().max(1) as f64;
+    let mut svg = String::new();
+    svg.push_str(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">\n", width, height));
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+    let status = if stats.status == Status::Mapped {format!("mapped, MAPQ {}", stats.mapq)} else {stats.status.name().to_string()};
+    svg.push_str(&format!("<text x=\"{}\" y=\"20\" font-weight=\"bold\">{} ({} bp, {})</text>\n", MARGIN, escape(q_id), stats.q_len, status));
+    // legend
+    for (i, (label, rc, kept)) in [("+ chained", false, true), ("- chained", true, true), ("+ filtered", false, false), ("- filtered", true, false)].iter().enumerate() {
+        let x = MARGIN + i as f64 * 110.0;
+        svg.push_str(&format!("<line x1=\"{}\" y1=\"32\" x2=\"{}\" y2=\"32\" stroke=\"{}\" stroke-width=\"3\"/><text x=\"{}\" y=\"36\">{}</text>\n", x, x + 20.0, color(*rc, *kept), x + 25.0, label));
+    }
+    if refs.is_empty() {
+        svg.push_str(&format!("<text x=\"{}\" y=\"80\">No matches.</text>\n", MARGIN));
+    }
+    for (p, (r_idx, count)) in refs.iter().enumerate() {
+        let rinfo = ref_map.get(r_idx).unwrap();
+        let anchors = stats.anchors.iter().filter(|(r, _, _)| r == r_idx).collect::<Vec<_>>();
+        let r_min = anchors.iter().map(|(_, h, _)| h.r_start).min().unwrap();
+        let r_max = anchors.iter().map(|(_, h, _)| h.r_end).max().unwrap();
+        let pad = ((r_max - r_min) / 20).max(stats.q_len / 20).max(1);
+        let (r_lo, r_hi) = (r_min.saturating_sub(pad), r_max + pad);
+        let (ox, oy) = (MARGIN, 40.0 + p as f64 * panel_h + MARGIN);
+        let x = |q: usize| ox + q as f64 / stats.q_len.max(1) as f64 * PLOT_SIZE;
+        let y = |r: usize| oy + PLOT_SIZE - (r - r_lo) as f64 / (r_hi - r_lo) as f64 * PLOT_SIZE;
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\">{} ({} matching k-min-mers)</text>\n", ox, oy - 10.0, escape(&rinfo.name), count));
+        svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#444\"/>\n", ox, oy, PLOT_SIZE, PLOT_SIZE));
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">query</text>\n", ox + PLOT_SIZE / 2.0, oy + PLOT_SIZE + 35.0));
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"start\">0</text><text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n", ox, oy + PLOT_SIZE + 15.0, ox + PLOT_SIZE, oy + PLOT_SIZE + 15.0, stats.q_len));
+        svg.push_str(&format!("<text transform=\"translate({},{}) rotate(-90)\" text-anchor=\"middle\">reference</text>\n", ox - 50.0, oy + PLOT_SIZE / 2.0));
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text><text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n", ox - 5.0, oy + PLOT_SIZE, r_lo, ox - 5.0, oy + 10.0, r_hi));
+        // filtered Matches first, so that chained ones are drawn on top
+        for (_, h, kept) in anchors.iter().filter(|a| !a.2).chain(anchors.iter().filter(|a| a.2)) {
+            let (y1, y2) = if h.rc {(y(h.r_end), y(h.r_start))} else {(y(h.r_start), y(h.r_end))};
+            svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"3\" stroke-linecap=\"round\"><title>q {}-{}, r {}-{}, {} k-min-mers</title></line>\n",
+                                 x(h.q_start), y1, x(h.q_end), y2, color(h.rc, *kept), h.q_start, h.q_end, h.r_start, h.r_end, h.count));
+        }
+    }
+    svg.push_str("</svg>\n");
+    let path = svg_path(output_prefix, q_id);
+    let mut f = match File::create(&path) {
+        Err(why) => panic!("Couldn't create {}: {}", path, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    f.write_all(svg.as_bytes()).expect("Error writing dot plot.");
+}
@@ -0,0 +1,220 @@
+// eval.rs
+// Contains the "eval" subcommand, which evaluates the mapping accuracy of simulated reads whose true position is encoded in their name
+// (pbsim/paftools or "mapquik simulate" format, e.g. S1_1!chr1!start!end!+), similarly to paftools mapeval.
+
+use crate::{closures::for_each_record, is_fasta_filename, paf::{self, PafRecord}};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct EvalOpt {
+    /// Mappings of simulated reads (PAF or SAM, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub mappings: PathBuf,
+    /// Simulated reads (FASTA/FASTQ)
+    ///
+    /// Used to count unmapped reads, and to stratify by
+    /// the simulated identity (identity=<x> in headers).
+    #[structopt(parse(from_os_str), long)]
+    pub reads: Option<PathBuf>,
+    /// Minimum overlap between true and mapped intervals
+    ///
+    /// A mapping is correct if it is on the true reference
+    /// and overlaps the true interval by at least this
+    /// fraction of their union.
+    #[structopt(long, default_value = "0.1")]
+    pub min_overlap: f64,
+    /// MAPQ threshold used in the length and identity tables
+    ///
+    #[structopt(long, default_value = "60")]
+    pub mapq: usize,
+    /// Comma-separated read length bin boundaries
+    ///
+    #[structopt(long, default_value = "5000,10000,15000,20000,30000")]
+    pub length_bins: String,
+    /// Comma-separated identity bin boundaries
+    ///
+    #[structopt(long, default_value = "0.9,0.95,0.97,0.98,0.99,0.995")]
+    pub identity_bins: String,
+}
+
+// True location of a simulated read (0-based, half-open).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Truth {
+    pub chrom: String,
+    pub start: usize,
+    pub end: usize,
+    pub rc: bool,
+}
+
+// Parse the true location from a read name of the form <name>!<chrom>!<start>!<end>!<strand> (0-based, half-open, as in paftools pbsim2fq names).
+pub fn parse_truth(name: &str) -> Option<Truth> {
+    let f : Vec<&str> = name.rsplitn(5, '!').collect();
+    if f.len() < 5 {return None;}
+    let rc = match f[0] {
+        "+" => false,
+        "-" => true,
+        _ => return None,
+    };
+    let end : usize = f[1].parse().ok()?;
+    let start : usize = f[2].parse().ok()?;
+    Some(Truth {chrom: f[3].to_string(), start, end, rc})
+}
+
+// Encode a true location (0-based, half-open) in a read name, in the format parsed by parse_truth.
+pub fn truth_name(name: &str, chrom: &str, start: usize, end: usize, rc: bool) -> String {
+    format!("{}!{}!{}!{}!{}", name, chrom, start, end, if rc {'-'} else {'+'})
+}
+
+// Parse the simulated identity from a FASTA/FASTQ header description (identity=<x>).
+pub fn parse_identity(desc: &str) -> Option<f64> {
+    desc.split_whitespace().find_map(|tag| tag.strip_prefix("identity=")).and_then(|x| x.parse().ok())
+}
+
+#[derive(Clone, Debug)]
+pub struct EvalRead {
+    pub len: usize, // Read length
+    pub identity: Option<f64>, // Simulated identity, or identity reported by the mapper
+    pub truth: Truth,
+    pub mapq: Option<usize>, // MAPQ of the primary mapping (None if unmapped)
+    pub correct: bool, // Primary mapping overlaps the true location
+}
+
+pub struct Evaluation {
+    pub reads: HashMap<String, EvalRead>,
+    pub nb_no_truth: usize, // mapped reads whose name doesn't encode a true location
+}
+
+impl Evaluation {
+
+    // Evaluate the primary mapping of each read against its true location.
+    pub fn new(records: &[PafRecord], reads_filename: Option<&PathBuf>, min_overlap: f64) -> Self {
+        let mut reads = HashMap::<String, EvalRead>::new();
+        let mut nb_no_truth = 0;
+        if let Some(filename) = reads_filename {
+            for_each_record(filename, is_fasta_filename(filename), |id, desc, seq| {
+                if let Some(truth) = parse_truth(id) {
+                    let identity = desc.and_then(parse_identity);
+                    reads.insert(id.to_string(), EvalRead {len: seq.len(), identity, truth, mapq: None, correct: false});
+                }
+            });
+        }
+        for rec in records.iter().filter(|r| r.primary) {
+            let r = match reads.get_mut(&rec.q_name) {
+                Some(r) => r,
+                None => {
+                    let truth = match parse_truth(&rec.q_name) {
+                        Some(t) => t,
+                        None => {nb_no_truth += 1; continue;}
+                    };
+                    reads.entry(rec.q_name.clone()).or_insert(EvalRead {len: rec.q_len, identity: None, truth, mapq: None, correct: false})
+                }
+            };
+            if r.mapq.is_some() {continue;} // only the first primary record of a read is evaluated
+            r.mapq = Some(rec.mapq);
+            if r.identity.is_none() {r.identity = rec.identity;}
+            r.correct = rec.t_name == r.truth.chrom && paf::overlap_ratio(rec.t_start, rec.t_end, r.truth.start, r.truth.end) >= min_overlap;
+        }
+        Evaluation {reads, nb_no_truth}
+    }
+
+    // Number of reads mapped with MAPQ >= min_mapq, and how many of those are wrong.
+    pub fn mapped_at(&self, min_mapq: usize) -> (usize, usize) {
+        self.reads.values().filter(|r| r.mapq.map_or(false, |q| q >= min_mapq)).fold((0, 0), |(m, w), r| (m + 1, w + !r.correct as usize))
+    }
+
+    // Print mapped reads and errors per MAPQ threshold (cumulative, as in paftools mapeval).
+    pub fn print_mapq_table(&self) {
+        let total = self.reads.len();
+        let mut mapqs = self.reads.values().filter_map(|r| r.mapq).collect::<Vec<usize>>();
+        mapqs.sort_unstable_by(|a, b| b.cmp(a));
+        mapqs.dedup();
+        println!("#Q\tmapq\tmapped\twrong\tcum_mapped\tcum_wrong\tcum_mapped_frac\tcum_error_rate");
+        let (mut cum_mapped, mut cum_wrong) = (0, 0);
+        for q in mapqs {
+            let (mapped, wrong) = self.reads.values().filter(|r| r.mapq == Some(q)).fold((0, 0), |(m, w), r| (m + 1, w + !r.correct as usize));
+            cum_mapped += mapped;
+            cum_wrong += wrong;
+            println!("Q\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{:.6}", q, mapped, wrong, cum_mapped, cum_wrong, ratio(cum_mapped, total), ratio(cum_wrong, cum_mapped));
+        }
+    }
+
+    // Print mapped reads and errors per bin of a read property (bins are [bounds[i-1], bounds[i])).
+    pub fn print_binned_table<F: Fn(&EvalRead) -> Option<f64>>(&self, tag: &str, name: &str, bounds: &[f64], min_mapq: usize, value: F) {
+        println!("#{}\tmin_{}\tmax_{}\treads\tmapped\twrong\tmapped_q{}\twrong_q{}\tmapped_frac\terror_rate\tmapped_frac_q{}\terror_rate_q{}", tag, name, name, min_mapq, min_mapq, min_mapq, min_mapq);
+        let nb_bins = bounds.len() + 1;
+        let mut counts = vec![[0usize; 5]; nb_bins]; // reads, mapped, wrong, mapped at min_mapq, wrong at min_mapq
+        let mut nb_unknown = 0;
+        for r in self.reads.values() {
+            let v = match value(r) {
+                Some(v) => v,
+                None => {nb_unknown += 1; continue;}
+            };
+            let b = bounds.iter().position(|&x| v < x).unwrap_or(bounds.len());
+            counts[b][0] += 1;
+            if let Some(q) = r.mapq {
+                counts[b][1] += 1;
+                counts[b][2] += !r.correct as usize;
+                if q >= min_mapq {
+                    counts[b][3] += 1;
+                    counts[b][4] += !r.correct as usize;
+                }
+            }
+        }
+        for (b, c) in counts.iter().enumerate() {
+            if c[0] == 0 {continue;}
+            let lo = if b == 0 {"-".to_string()} else {bounds[b - 1].to_string()};
+            let hi = if b == bounds.len() {"-".to_string()} else {bounds[b].to_string()};
+            println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{:.6}\t{:.6}\t{:.6}", tag, lo, hi, c[0], c[1], c[2], c[3], c[4], ratio(c[1], c[0]), ratio(c[2], c[1]), ratio(c[3], c[0]), ratio(c[4], c[3]));
+        }
+        if nb_unknown > 0 {println!("#{}\t{} reads without {}", tag, nb_unknown, name);}
+    }
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 {0.0} else {a as f64 / b as f64}
+}
+
+// Parse comma-separated bin boundaries.
+pub fn parse_bins(s: &str) -> Vec<f64> {
+    let mut bins = s.split(',').filter(|x| !x.is_empty()).map(|x| x.trim().parse::<f64>().expect("Invalid bin boundary.")).collect::<Vec<f64>>();
+    bins.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    bins
+}
+
+// Run the eval subcommand.
+pub fn run(opt: &EvalOpt) {
+    let records = paf::read_records(&opt.mappings);
+    let eval = Evaluation::new(&records, opt.reads.as_ref(), opt.min_overlap);
+    let (mapped, wrong) = eval.mapped_at(0);
+    let (mapped_q, wrong_q) = eval.mapped_at(opt.mapq);
+    println!("#reads: {}, mapped: {} (wrong: {}), mapped at Q{}: {} (wrong: {})", eval.reads.len(), mapped, wrong, opt.mapq, mapped_q, wrong_q);
+    if eval.nb_no_truth > 0 {println!("#Warning: {} mapped reads have no true location in their name and were ignored.", eval.nb_no_truth);}
+    eval.print_mapq_table();
+    eval.print_binned_table("L", "len", &parse_bins(&opt.length_bins), opt.mapq, |r| Some(r.len as f64));
+    eval.print_binned_table("I", "identity", &parse_bins(&opt.identity_bins), opt.mapq, |r| r.identity);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truth_name_round_trip_keeps_0_based_start() {
+        let name = truth_name("S1_1", "chr1", 0, 1500, true);
+        assert_eq!(name, "S1_1!chr1!0!1500!-");
+        let t = parse_truth(&name).unwrap();
+        assert_eq!((t.chrom.as_str(), t.start, t.end, t.rc), ("chr1", 0, 1500, true));
+    }
+
+    #[test]
+    fn parse_truth_reads_pbsim2fq_names() {
+        // read names may contain '!' themselves
+        let t = parse_truth("m1!read!chr2!100!250!+").unwrap();
+        assert_eq!((t.chrom.as_str(), t.start, t.end, t.rc), ("chr2", 100, 250, false));
+        assert!(parse_truth("chr2!100!250!+").is_none());
+        assert!(parse_truth("read!chr2!100!250!?").is_none());
+    }
+}
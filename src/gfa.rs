@@ -0,0 +1,195 @@
+// gfa.rs
+// Mapping to a pangenome graph (GFA): the sequences spelled by the paths (P lines) and walks (W lines) of the graph are indexed as references,
+// so that k-min-mers spanning segment boundaries are indexed, and k-min-mers shared by paths are kept on every path traversing their graph locus.
+// Mappings are chained along each path as on a linear reference; chains on paths traversing the same segments are the same placement,
+// and the best one is converted to a GAF record on the oriented segments it traverses.
+// Segment overlaps in links are ignored (blunt graphs, as produced by minigraph-cactus or pggb, are expected).
+
+use crate::{Params, PseudoChainCoordsTuple, get_reader, mers};
+use crate::index::{Entry, Index, ReadOnlyIndex, RefInfo};
+use crate::summary::Summary;
+use dashmap::DashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::Instant;
+
+// A path through the graph: oriented segments (segment index, reverse), and the start of each step on the path (plus the path length).
+pub struct GraphPath {
+    pub name: String,
+    pub steps: Vec<(usize, bool)>,
+    pub starts: Vec<usize>,
+}
+impl GraphPath {
+
+    // Create a new GraphPath from its steps, given the segment lengths.
+    pub fn new(name: &str, steps: Vec<(usize, bool)>, seg_lens: &[usize]) -> Self {
+        let mut starts = Vec::with_capacity(steps.len() + 1);
+        starts.push(0);
+        for &(s, _) in steps.iter() {starts.push(starts[starts.len() - 1] + seg_lens[s]);}
+        GraphPath {name: name.to_string(), steps, starts}
+    }
+
+    // Path length.
+    pub fn len(&self) -> usize {
+        self.starts[self.starts.len() - 1]
+    }
+
+    // Step containing a path position.
+    pub fn step_at(&self, pos: usize) -> usize {
+        self.starts.partition_point(|&s| s <= pos) - 1
+    }
+}
+
+// A GFA graph: segment names and lengths, and the paths that are indexed.
+pub struct Graph {
+    pub seg_names: Vec<String>,
+    pub seg_lens: Vec<usize>,
+    pub paths: Vec<GraphPath>,
+    path_ids: HashMap<String, usize>, // path index by name
+}
+impl Graph {
+
+    // Load a GFA file (raw or compressed). Returns the Graph and the segment sequences.
+    // Segments not traversed by any path or walk are added as single-segment paths, so that they can still be mapped to.
+    pub fn load(path: &PathBuf) -> (Self, Vec<Vec<u8>>) {
+        let (buf, _) = get_reader(path);
+        let mut seg_ids = HashMap::<String, usize>::new();
+        let (mut seg_names, mut seqs) = (Vec::new(), Vec::new());
+        let mut raw_paths = Vec::<(String, Vec<(String, bool)>)>::new();
+        for line in buf.lines() {
+            let line = line.expect("Error reading GFA file.");
+            let fields : Vec<&str> = line.split('\t').collect();
+            match fields[0] {
+                "S" => {
+                    if fields.len() < 3 {panic!("Malformed S line in GFA file: {}", line);}
+                    if fields[2] == "*" {panic!("GFA segment {} has no sequence.", fields[1]);}
+                    seg_ids.insert(fields[1].to_string(), seg_names.len());
+                    seg_names.push(fields[1].to_string());
+                    seqs.push(fields[2].as_bytes().to_ascii_uppercase());
+                }
+                "P" => {
+                    if fields.len() < 3 {panic!("Malformed P line in GFA file: {}", line);}
+                    let steps = fields[2].split(',').filter(|s| !s.is_empty()).map(|s| {
+                        let (name, orient) = s.split_at(s.len() - 1);
+                        (name.to_string(), orient == "-")
+                    }).collect();
+                    raw_paths.push((fields[1].to_string(), steps));
+                }
+                "W" => {
+                    if fields.len() < 7 {panic!("Malformed W line in GFA file: {}", line);}
+                    let mut name = format!("{}#{}#{}", fields[1], fields[2], fields[3]);
+                    if fields[4] != "*" {name.push_str(&format!(":{}-{}", fields[4], fields[5]));}
+                    let mut steps = Vec::new();
+                    for (i, c) in fields[6].char_indices().filter(|(_, c)| *c == '>' || *c == '<') {
+                        let end = fields[6][i + 1..].find(|c| c == '>' || c == '<').map_or(fields[6].len(), |j| i + 1 + j);
+                        steps.push((fields[6][i + 1..end].to_string(), c == '<'));
+                    }
+                    raw_paths.push((name, steps));
+                }
+                _ => {}
+            }
+        }
+        let seg_lens = seqs.iter().map(|s| s.len()).collect::<Vec<usize>>();
+        let mut traversed = vec![false; seg_names.len()];
+        let mut paths = Vec::new();
+        for (name, steps) in raw_paths {
+            let steps = steps.iter().map(|(s, rev)| {
+                let id = *seg_ids.get(s).unwrap_or_else(|| panic!("Path {} traverses unknown segment {}.", name, s));
+                traversed[id] = true;
+                (id, *rev)
+            }).collect::<Vec<(usize, bool)>>();
+            if !steps.is_empty() {paths.push(GraphPath::new(&name, steps, &seg_lens));}
+        }
+        for (id, t) in traversed.iter().enumerate() {
+            if !t {paths.push(GraphPath::new(&seg_names[id], vec![(id, false)], &seg_lens));}
+        }
+        let path_ids = paths.iter().enumerate().map(|(i, p)| (p.name.clone(), i)).collect();
+        (Graph {seg_names, seg_lens, paths, path_ids}, seqs)
+    }
+
+    // Sequence spelled by a path.
+    pub fn path_seq(&self, p: &GraphPath, seqs: &[Vec<u8>]) -> Vec<u8> {
+        let mut seq = Vec::with_capacity(p.len());
+        for &(s, rev) in p.steps.iter() {
+            if rev {seq.extend(bio::alphabets::dna::revcomp(&seqs[s]));} else {seq.extend_from_slice(&seqs[s]);}
+        }
+        seq
+    }
+
+    // Graph position (segment, forward offset) of a path position.
+    fn graph_pos(&self, p: &GraphPath, pos: usize) -> (usize, usize) {
+        let i = p.step_at(pos);
+        let (s, rev) = p.steps[i];
+        let d = pos - p.starts[i];
+        (s, if rev {self.seg_lens[s] - 1 - d} else {d})
+    }
+
+    // Graph locus of an Entry: graph positions of its first and last bases, in either order (paths may traverse segments in either orientation).
+    fn locus(&self, e: &Entry) -> ((usize, usize), (usize, usize)) {
+        let p = &self.paths[e.id];
+        let (a, b) = (self.graph_pos(p, e.start), self.graph_pos(p, e.end - 1));
+        if a <= b {(a, b)} else {(b, a)}
+    }
+
+    // Best chain of a read among chains on paths (reference IDs are path indices). Chains on paths traversing common segments are the same placement:
+    // the one with most matches, then on the first path, is kept. Ties with chains at other placements are ambiguous.
+    pub fn best_path_match<'a>(&self, all_pseudocoords: &[PseudoChainCoordsTuple<'a>]) -> Option<PseudoChainCoordsTuple<'a>> {
+        let segs = all_pseudocoords.iter().map(|(r, c)| {
+            let p = &self.paths[*r];
+            let (i0, i1) = (p.step_at(c.3), p.step_at(c.4.max(c.3 + 1).min(p.len()) - 1));
+            p.steps[i0..=i1].iter().map(|&(s, _)| s).collect::<HashSet<usize>>()
+        }).collect::<Vec<HashSet<usize>>>();
+        let best = (0..all_pseudocoords.len()).max_by_key(|&i| (all_pseudocoords[i].1.5, Reverse(all_pseudocoords[i].0))).unwrap();
+        let next_max_count = (0..all_pseudocoords.len()).filter(|&i| i != best && segs[i].is_disjoint(&segs[best])).map(|i| all_pseudocoords[i].1.5).max().unwrap_or(0);
+        if next_max_count == all_pseudocoords[best].1.5 {return None;}
+        Some(all_pseudocoords[best])
+    }
+
+    // Convert mapping lines (PAF, on a path) into GAF lines on the oriented segments traversed by the mapping.
+    // GAF coordinates are 0-based, half-open; the mapping's tags are kept.
+    pub fn gaf(&self, paf: &str) -> String {
+        paf.split('\n').map(|line| {
+            let fields : Vec<&str> = line.split('\t').collect();
+            let p = &self.paths[*self.path_ids.get(fields[5]).unwrap()];
+            let r_start = fields[7].parse::<usize>().unwrap();
+            let r_end = fields[8].parse::<usize>().unwrap() + 1;
+            let q_end = fields[3].parse::<usize>().unwrap() + 1;
+            let (i0, i1) = (p.step_at(r_start), p.step_at(r_end - 1));
+            let walk = p.steps[i0..=i1].iter().map(|&(s, rev)| format!("{}{}", if rev {'<'} else {'>'}, self.seg_names[s])).collect::<String>();
+            let offset = p.starts[i0];
+            let mut gaf = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", fields[0], fields[1], fields[2], q_end, fields[4], walk, p.starts[i1 + 1] - offset,
+                r_start - offset, r_end - offset, fields[9], r_end - r_start, fields[11]);
+            for tag in fields[12..].iter() {gaf.push('\t'); gaf.push_str(tag);}
+            gaf
+        }).collect::<Vec<String>>().join("\n")
+    }
+}
+
+// Index the k-min-mers of all paths of the graph. K-min-mers shared by several paths (same graph locus) are kept on each of them (see Index::add_with_locus).
+// Returns the read-only Index and the RefInfo of each path (reference IDs are path indices), and records index counts and time in the Summary.
+pub fn build_index(graph: &Graph, seqs: &[Vec<u8>], params: &Params, summary: &mut Summary) -> (ReadOnlyIndex, DashMap<usize, RefInfo>) {
+    let mers_index = Index::new();
+    let ref_map : DashMap<usize, RefInfo> = DashMap::new();
+    let start = Instant::now();
+    for (id, p) in graph.paths.iter().enumerate() {
+        let seq = graph.path_seq(p, seqs);
+        let mut nb_mers = 0;
+        if let Some(iter) = mers::extract(&p.name, &seq, params) {
+            for kminmer in iter {
+                mers_index.add_with_locus(id, &kminmer, |a, b| graph.locus(a) == graph.locus(b));
+                nb_mers += 1;
+            }
+        }
+        ref_map.insert(id, RefInfo::new(&p.name, seq.len(), false, false, 0));
+        println!("Indexed path {}: {} k-min-mers ({} segments).", p.name, nb_mers, p.steps.len());
+    }
+    let duration = start.elapsed();
+    let (nb_unique, nb_repeated) = mers_index.get_counts();
+    println!("Indexed {} unique k-min-mers from {} segments and {} paths in {:?}.", nb_unique, graph.seg_names.len(), graph.paths.len(), duration);
+    summary.nb_unique = nb_unique;
+    summary.nb_repeated = nb_repeated;
+    summary.index_duration = duration;
+    (ReadOnlyIndex::new(mers_index), ref_map)
+}
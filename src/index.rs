@@ -4,6 +4,7 @@
 use crate::{KH};
 use rust_seq2kminmers::{KminmerType as Kminmer, KminmerHash};
 use dashmap::{DashMap, ReadOnlyView};
+use dashmap::mapref::entry::Entry as MapEntry;
 use std::hash::BuildHasherDefault;
 use core::hash::Hasher;
 
@@ -46,21 +47,22 @@ pub struct Entry {
     pub end: usize, // End location
     pub offset: usize, // K-min-mer offset (index in the k-min-mer array)
     pub rc: bool, // Strand direction
+    pub hap: u8, // Haplotypes containing the k-min-mer (bit mask, 0 for references without haplotype)
 }
 impl Entry {
 
     // Create a new Entry.
-    pub fn new(id: usize, start: usize, end: usize, offset: usize, rc: bool) -> Self {
-        Entry {id, start, end, offset, rc}
+    pub fn new(id: usize, start: usize, end: usize, offset: usize, rc: bool, hap: u8) -> Self {
+        Entry {id, start, end, offset, rc, hap}
     }
 
-    pub fn new_with_mer(id: usize, mer: &KminmerHash) -> Self {
-        Entry {id, start: mer.start, end: mer.end, offset:mer.offset, rc: mer.rev}
+    pub fn new_with_mer(id: usize, mer: &KminmerHash, hap: u8) -> Self {
+        Entry {id, start: mer.start, end: mer.end, offset:mer.offset, rc: mer.rev, hap}
     }
 
     // An empty Entry.
     pub fn empty() -> Self {
-        Entry {id: 0, start: 0, end: 0, offset: 0, rc: false}
+        Entry {id: 0, start: 0, end: 0, offset: 0, rc: false, hap: 0}
     }
 
     // Check if this Entry is Empty.
@@ -69,10 +71,30 @@ impl Entry {
     }
 }
 
+// A RefInfo object holds the name and length of a reference sequence, along with how it should be treated during mapping.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefInfo {
+    pub name: String, // Reference name
+    pub len: usize, // Sequence length
+    pub decoy: bool, // Hits are consumed but not reported
+    pub circular: bool, // Sequence is circular (k-min-mers and mappings wrap around the origin)
+    pub hap: u8, // Haplotype bit (0 if none, see Mask::load_haplotypes)
+}
+impl RefInfo {
+
+    // Create a new RefInfo.
+    pub fn new(name: &str, len: usize, decoy: bool, circular: bool, hap: u8) -> Self {
+        RefInfo {name: name.to_string(), len, decoy, circular, hap}
+    }
+}
+
 // An Index object is a mapping of k-min-mer hashes (see kminmer.rs) to a single Entry (multiple Entries are not allowed).
+// K-min-mers that are the same sequence at several loci (once per haplotype, or on several graph paths sharing segments) are not repeats:
+// their other loci are kept in alt.
 pub struct Index {
     //pub index: Arc<DashMap<H, Entry, BuildHasherDefault<FxHasher64>>>
     pub index: DashMap<KH, Entry, BuildHasherDefault<KnownHasher>>,
+    pub alt: DashMap<KH, Vec<Entry>, BuildHasherDefault<KnownHasher>>,
 }
 impl Index {
 
@@ -84,6 +106,7 @@ impl Index {
                                                                                          hasher);
         Index {
             index: map,
+            alt: DashMap::with_hasher(BuildHasherDefault::<KnownHasher>::default()),
         }
     }
 
@@ -91,27 +114,74 @@ impl Index {
         self.index.iter().fold(0, |acc, x| (if !x.value().is_empty() {return acc + 1;} else {return acc;}))
     }
 
-    // Add an Entry to the Index. If an Entry for the hash h already exists, insert None to prevent duplicates.
-    pub fn add(&self, h: KH, id: usize, start: usize, end: usize, offset: usize, rc: bool) {
-        let e = self.index.insert(h, Entry::new(id, start, end, offset, rc));
-        if e.is_some() {self.index.insert(h, Entry::empty());}
+    // Get the number of unique and repeated k-min-mers.
+    pub fn get_counts(&self) -> (usize, usize) {
+        let total = self.index.len();
+        let unique = self.get_count();
+        (unique, total - unique)
     }
 
-    pub fn add_with_mer(&self, id: usize, mer: &Kminmer) {
-        let h = mer.hash;
-        let e = self.index.insert(h, Entry::new_with_mer(id, mer));
-        if e.is_some() {self.index.insert(h, Entry::empty());}
+    // Add an Entry to the Index. If an Entry for the hash h already exists, insert None to prevent duplicates (see insert).
+    pub fn add(&self, h: KH, id: usize, start: usize, end: usize, offset: usize, rc: bool, hap: u8) {
+        self.insert(h, Entry::new(id, start, end, offset, rc, hap));
+    }
+
+    pub fn add_with_mer(&self, id: usize, mer: &Kminmer, hap: u8) {
+        self.insert(mer.hash, Entry::new_with_mer(id, mer, hap));
+    }
+
+    // Add an Entry for a k-min-mer of a graph path. Occurrences at the same graph locus (on paths sharing segments, see same_locus)
+    // are the same k-min-mer: the first one is stored and the others are kept in alt, so that every path traversing the locus has it.
+    // Occurrences at different loci are repeats.
+    pub fn add_with_locus<F: Fn(&Entry, &Entry) -> bool>(&self, id: usize, mer: &Kminmer, same_locus: F) {
+        let e = Entry::new_with_mer(id, mer, 0);
+        match self.index.entry(mer.hash) {
+            MapEntry::Vacant(v) => {v.insert(e);}
+            MapEntry::Occupied(mut o) => {
+                let prev = o.get_mut();
+                if prev.is_empty() {return;}
+                if same_locus(prev, &e) {self.alt.entry(mer.hash).or_insert_with(Vec::new).push(e);}
+                else {
+                    *prev = Entry::empty();
+                    self.alt.remove(&mer.hash);
+                }
+            }
+        }
+    }
+
+    // Insert an Entry, or an empty Entry if the hash already exists. A k-min-mer occurring once on each haplotype is not a repeat:
+    // it is stored at its position on the lowest haplotype and its position on the other one is kept in alt, both with the bits of the two haplotypes set.
+    fn insert(&self, h: KH, e: Entry) {
+        match self.index.entry(h) {
+            MapEntry::Vacant(v) => {v.insert(e);}
+            MapEntry::Occupied(mut o) => {
+                let prev = o.get_mut();
+                if prev.hap != 0 && e.hap != 0 && prev.hap & e.hap == 0 {
+                    let hap = prev.hap | e.hap;
+                    let mut other = if e.hap < prev.hap {std::mem::replace(prev, e)} else {e};
+                    prev.hap = hap;
+                    other.hap = hap;
+                    self.alt.insert(h, vec![other]);
+                }
+                else {
+                    *prev = Entry::empty();
+                    self.alt.remove(&h);
+                }
+            }
+        }
     }
 }
             
 
 pub struct ReadOnlyIndex {
-    pub read_only_index : ReadOnlyView<KH, Entry, BuildHasherDefault<KnownHasher>>
+    pub read_only_index : ReadOnlyView<KH, Entry, BuildHasherDefault<KnownHasher>>,
+    pub alt : ReadOnlyView<KH, Vec<Entry>, BuildHasherDefault<KnownHasher>>,
 }
 impl ReadOnlyIndex {
-    pub fn new(index : DashMap<KH, Entry, BuildHasherDefault<KnownHasher>>) -> Self {
+    pub fn new(index : Index) -> Self {
         ReadOnlyIndex {
-            read_only_index: index.into_read_only()
+            read_only_index: index.index.into_read_only(),
+            alt: index.alt.into_read_only(),
         }
     }
     // Return the Entry associated with the k-min-mer hash h, or None if none.
@@ -124,5 +194,63 @@ impl ReadOnlyIndex {
         }
         None
     }
+
+    // Return the Entry associated with the k-min-mer hash h, including empty Entries of repeated k-min-mers, or None if none.
+    pub fn get_raw(&self, h: &KH) -> Option<&Entry> {
+        self.read_only_index.get(h)
+    }
+
+    // Return the other loci of the k-min-mer hash h (see Index::alt).
+    pub fn get_alt(&self, h: &KH) -> &[Entry] {
+        match self.alt.get(h) {
+            Some(v) => v,
+            None => &[],
+        }
+    }
+
+    // Check if some k-min-mers have several loci.
+    pub fn has_alt(&self) -> bool {
+        !self.alt.is_empty()
+    }
     
 }
+
+// A MultiIndex maps k-min-mer hashes to all their occurrences (used to find read-to-read overlaps, where every k-min-mer is expected to occur once per overlapping read).
+pub struct MultiIndex {
+    pub index: DashMap<KH, Vec<Entry>, BuildHasherDefault<KnownHasher>>,
+}
+impl MultiIndex {
+
+    // Create a new MultiIndex.
+    pub fn new() -> Self {
+        MultiIndex {
+            index: DashMap::with_hasher(BuildHasherDefault::<KnownHasher>::default()),
+        }
+    }
+
+    // Add an occurrence of a k-min-mer.
+    pub fn add_with_mer(&self, id: usize, mer: &Kminmer) {
+        self.index.entry(mer.hash).or_insert_with(Vec::new).push(Entry::new_with_mer(id, mer, 0));
+    }
+
+    // Drop k-min-mers occurring more than max_occ times (repeats), and return a read-only view along with the number of dropped k-min-mers.
+    pub fn into_read_only(self, max_occ: usize) -> (ReadOnlyMultiIndex, usize) {
+        let total = self.index.len();
+        self.index.retain(|_, v| v.len() <= max_occ);
+        let dropped = total - self.index.len();
+        (ReadOnlyMultiIndex {read_only_index: self.index.into_read_only()}, dropped)
+    }
+}
+
+pub struct ReadOnlyMultiIndex {
+    pub read_only_index : ReadOnlyView<KH, Vec<Entry>, BuildHasherDefault<KnownHasher>>
+}
+impl ReadOnlyMultiIndex {
+    // Return all occurrences of the k-min-mer hash h.
+    pub fn get(&self, h: &KH) -> &[Entry] {
+        match self.read_only_index.get(h) {
+            Some(v) => v,
+            None => &[],
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// inspect.rs
+// Contains the "inspect" subcommand, which prints the k-min-mers of a single read (or reference region), the index Entries they hit,
+// and the resulting Matches and Chains, to debug why a sequence maps (or doesn't) where it does.
+
+use crate::{Params, chain::Chain, closures, index::ReadOnlyIndex, is_fasta_filename, mask::Mask, mers, presets::SeedingOpt, stats::Stats, summary::Summary};
+use rust_seq2kminmers::Kminmer;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct InspectOpt {
+    /// Reference genome input
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub reference: PathBuf,
+    /// Reads file (FASTA/FASTQ, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub reads: Option<PathBuf>,
+    /// Name of the read to inspect (default: first read)
+    ///
+    #[structopt(long)]
+    pub read: Option<String>,
+    /// Reference region to inspect instead of a read
+    ///
+    /// Given as <name>:<start>-<end> (1-based, inclusive),
+    /// or <name> for a whole reference.
+    #[structopt(long)]
+    pub region: Option<String>,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+    /// Minimum chain length
+    ///
+    #[structopt(short, long)]
+    pub chain: Option<usize>,
+    /// Minimum number of matching seeds
+    ///
+    #[structopt(short, long)]
+    pub seed: Option<usize>,
+    /// Comma-separated names of circular references
+    ///
+    #[structopt(long)]
+    pub circular: Option<String>,
+}
+
+// Parse a region of the form <name>:<start>-<end> (1-based, inclusive) into a name and a 0-based, half-open interval (None for the whole sequence).
+pub fn parse_region(region: &str) -> (String, Option<(usize, usize)>) {
+    let (name, range) = match region.rsplit_once(':') {
+        Some((name, range)) if range.contains('-') => (name, range),
+        _ => return (region.to_string(), None),
+    };
+    let (start, end) = range.split_once('-').unwrap();
+    let start : usize = start.replace(',', "").parse().expect("Invalid region start.");
+    let end : usize = end.replace(',', "").parse().expect("Invalid region end.");
+    if start == 0 || end < start {panic!("Invalid region: {}.", region);}
+    (name.to_string(), Some((start - 1, end)))
+}
+
+// Get the sequence to inspect: a reference region, or a read (the first one if no name is given).
+fn get_sequence(opt: &InspectOpt) -> (String, Vec<u8>) {
+    let (filename, name, interval) = match &opt.region {
+        Some(region) => {
+            let (name, interval) = parse_region(region);
+            (&opt.reference, Some(name), interval)
+        }
+        None => (opt.reads.as_ref().expect("Please specify a read file (--reads) or a reference region (--region)."), opt.read.clone(), None),
+    };
+    let mut found : Option<(String, Vec<u8>)> = None;
+    closures::for_each_record(filename, is_fasta_filename(filename), |id, _desc, seq| {
+        if found.is_some() || name.as_ref().map_or(false, |n| n != id) {return;}
+        let seq = match interval {
+            Some((start, end)) => {
+                if end > seq.len() {panic!("Region end ({}) is past the end of {} ({} bp).", end, id, seq.len());}
+                &seq[start..end]
+            }
+            None => seq,
+        };
+        let seq_name = match interval {
+            Some((start, end)) => format!("{}:{}-{}", id, start + 1, end),
+            None => id.to_string(),
+        };
+        found = Some((seq_name, seq.to_vec()));
+    });
+    match found {
+        Some(f) => f,
+        None => panic!("Sequence {} not found in {}.", name.unwrap_or_default(), filename.display()),
+    }
+}
+
+// Print each k-min-mer of the sequence and the index Entry it hits.
+fn print_kminmers(q_str: &[u8], params: &Params, index: &ReadOnlyIndex, ref_names: &[String]) {
+    println!("#kminmers\thash\tq_start\tq_end\toffset\tstrand\tstatus\tref\tr_start\tr_end\tr_offset\tr_strand");
+    let (mut nb_unique, mut nb_repeated, mut nb_absent) = (0, 0, 0);
+    if let Some(iter) = mers::extract("", q_str, params) {
+        for q in iter {
+            let strand = if q.rev {"-"} else {"+"};
+            let status = match index.get_raw(&q.get_hash()) {
+                Some(e) if e.is_empty() => {nb_repeated += 1; "repeated\t*\t*\t*\t*\t*".to_string()}
+                Some(e) => {nb_unique += 1; format!("unique\t{}\t{}\t{}\t{}\t{}", ref_names[e.id], e.start, e.end, e.offset, if e.rc {"-"} else {"+"})}
+                None => {nb_absent += 1; "absent\t*\t*\t*\t*\t*".to_string()}
+            };
+            println!("K\t{}\t{}\t{}\t{}\t{}\t{}", q.get_hash(), q.start, q.end, q.offset, strand, status);
+        }
+    }
+    println!("#k-min-mers: {} unique, {} repeated, {} absent", nb_unique, nb_repeated, nb_absent);
+}
+
+// Run the inspect subcommand.
+pub fn run(opt: &InspectOpt) {
+    let preset = opt.seeding.preset.get("hifi");
+    let mut params = opt.seeding.to_params("hifi");
+    let threads = opt.seeding.preset.threads;
+    params.c = opt.chain.unwrap_or(preset.c);
+    params.s = opt.seed.unwrap_or(preset.s);
+    let mut circular_names = HashSet::<String>::new();
+    if let Some(names) = &opt.circular {circular_names = names.split(',').filter(|n| !n.is_empty()).map(|n| n.to_string()).collect();}
+    let (q_id, q_str) = get_sequence(opt);
+    let mut summary = Summary::new();
+    let (index, ref_map) = closures::build_index(&opt.reference, &params, threads, threads, is_fasta_filename(&opt.reference), &Mask::new(), &circular_names, &mut summary);
+    let mut ref_names = vec![String::new(); ref_map.len()];
+    for r in ref_map.iter() {ref_names[*r.key()] = r.name.clone();}
+    println!("#sequence: {} ({} bp), k={}, l={}, density={}, hpc={}", q_id, q_str.len(), params.k, params.l, params.density, params.use_hpc);
+    print_kminmers(&q_str, &params, &index, &ref_names);
+
+    // Matches per reference, before and after chaining
+    let mut stats = Stats::new(&q_id, q_str.len(), true);
+    let mut kminmers = mers::extract(&q_id, &q_str, &params);
+    let mut matches_per_ref = mers::chain_matches(&q_id, &mut kminmers, &index, &mut stats).into_iter().collect::<Vec<_>>();
+    matches_per_ref.sort_unstable_by_key(|(r_id, _)| *r_id);
+    println!("#matches\tref\tq_start\tq_end\tr_start\tr_end\tcount\tstrand");
+    for (r_id, matches) in matches_per_ref.iter_mut() {
+        let rinfo = ref_map.get(r_id).unwrap();
+        if rinfo.circular {mers::unwrap_circular(matches, rinfo.len);}
+        for h in matches.iter() {
+            println!("M\t{}\t{}\t{}\t{}\t{}\t{}\t{}", rinfo.name, h.q_start, h.q_end, h.r_start, h.r_end, h.count, if h.rc {"-"} else {"+"});
+        }
+    }
+    println!("#chains\tref\tnb_matches\tq_start\tq_end\tr_start\tr_end\tscore\tmapq\tstrand");
+    for (r_id, matches) in matches_per_ref.iter() {
+        let mut c = Chain::new(matches);
+        let name = &ref_map.get(r_id).unwrap().name;
+        match c.get_match(&params) {
+            Some((rc, q_start, q_end, r_start, r_end, score, mapq)) => {
+                println!("C\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", name, c.len(), q_start, q_end, r_start, r_end, score, mapq, if rc {"-"} else {"+"});
+                for i in 0..c.len() {
+                    let h = c.nth(i);
+                    println!("CM\t{}\t{}\t{}\t{}\t{}\t{}\t{}", name, h.q_start, h.q_end, h.r_start, h.r_end, h.count, if h.rc {"-"} else {"+"});
+                }
+            }
+            None => println!("C\t{}\t0\t*\t*\t*\t*\t0\t0\t*", name),
+        }
+    }
+
+    // Final placement, as reported in the PAF
+    let mut stats = Stats::new(&q_id, q_str.len(), true);
+    let paf_line = mers::find_matches(&q_id, q_str.len(), &q_str, &ref_map, &index, &params, None, &mut stats);
+    println!("#status: {}", stats.status.name());
+    if let Some(l) = paf_line {println!("{}", l);}
+}
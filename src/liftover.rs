@@ -0,0 +1,108 @@
+// liftover.rs
+// Converts asm Blocks into UCSC chain records (ungapped segments separated by gaps), to lift reference coordinates over to contigs with standard liftOver tools.
+// Segments either come directly from Match runs, or from base-level alignments between consecutive seed anchors (--refine).
+
+use crate::{Params, asm::Block, index::ReadOnlyIndex, mers};
+use bio::alignment::AlignmentOperation;
+use bio::alignment::pairwise::Aligner;
+use rust_seq2kminmers::Kminmer;
+
+// An ungapped segment: reference start, query start (on the query strand of the block), size.
+pub type Segment = (usize, usize, usize);
+
+// Query interval of a block on its own strand (reverse-complemented coordinates for reverse blocks, as in the chain format).
+fn strand_interval(q_len: usize, rc: bool, q_start: usize, q_end: usize) -> (usize, usize) {
+    if rc {(q_len - q_end, q_len - q_start)} else {(q_start, q_end)}
+}
+
+// Append a segment, clipping it so that segments stay strictly increasing in both coordinates, and merging it with the previous one when they are contiguous.
+fn push_segment(segments: &mut Vec<Segment>, (r, q, size): Segment) {
+    let (r, q, size) = match segments.last() {
+        Some(&(pr, pq, ps)) => {
+            let clip = (pr + ps).saturating_sub(r).max((pq + ps).saturating_sub(q));
+            if clip >= size {return;}
+            (r + clip, q + clip, size - clip)
+        }
+        None => (r, q, size),
+    };
+    if size == 0 {return;}
+    match segments.last_mut() {
+        Some(last) if last.0 + last.2 == r && last.1 + last.2 == q => last.2 += size,
+        _ => segments.push((r, q, size)),
+    }
+}
+
+// Ungapped segments of a block taken from its Match runs (a Match whose query and reference spans differ is truncated to the shorter one).
+pub fn match_segments(b: &Block, q_len: usize) -> Vec<Segment> {
+    let mut matches = b.matches.iter().collect::<Vec<_>>();
+    matches.sort_unstable_by_key(|h| h.r_start);
+    let mut segments = Vec::new();
+    for h in matches.iter() {
+        let (q, _) = strand_interval(q_len, b.rc, h.q_start, h.q_end);
+        push_segment(&mut segments, (h.r_start, q, (h.r_end - h.r_start).min(h.q_end - h.q_start)));
+    }
+    segments
+}
+
+// Seed anchors of a block: (reference, query) positions where one of its k-min-mers starts on both sequences, increasing in both coordinates,
+// followed by the end of the block.
+pub fn anchors(b: &Block, q_str: &[u8], params: &Params, index: &ReadOnlyIndex) -> Vec<(usize, usize)> {
+    let q_len = q_str.len();
+    let mut anchors = Vec::<(usize, usize)>::new();
+    if let Some(iter) = mers::extract("", q_str, params) {
+        for q in iter {
+            if q.start < b.q_start || q.end > b.q_end {continue;}
+            if let Some(e) = index.get(&q.get_hash()) {
+                if e.id != b.r_idx || (q.rev != e.rc) != b.rc || e.start < b.r_start || e.end > b.r_end {continue;}
+                anchors.push((e.start, strand_interval(q_len, b.rc, q.start, q.end).0));
+            }
+        }
+    }
+    anchors.sort_unstable();
+    let mut increasing = Vec::<(usize, usize)>::new();
+    for a in anchors {
+        if increasing.last().map_or(true, |l| a.0 > l.0 && a.1 > l.1) {increasing.push(a);}
+    }
+    let end = (b.r_end, strand_interval(q_len, b.rc, b.q_start, b.q_end).1);
+    if increasing.last().map_or(false, |l| end.0 > l.0 && end.1 > l.1) {increasing.push(end);}
+    increasing
+}
+
+// Ungapped segments from global alignments of the reference and query between consecutive anchors.
+// Regions longer than max_len are not aligned: they contribute a single segment truncated to the shorter side, as in match_segments.
+pub fn refined_segments(anchors: &[(usize, usize)], r_str: &[u8], q_str: &[u8], max_len: usize) -> Vec<Segment> {
+    let score = |a: u8, b: u8| if a == b {1i32} else {-1i32};
+    let mut aligner = Aligner::with_capacity(max_len, max_len, -5, -1, &score);
+    let mut segments = Vec::new();
+    for w in anchors.windows(2) {
+        let ((r0, q0), (r1, q1)) = (w[0], w[1]);
+        if (r1 - r0).max(q1 - q0) > max_len {
+            push_segment(&mut segments, (r0, q0, (r1 - r0).min(q1 - q0)));
+            continue;
+        }
+        let alignment = aligner.global(&q_str[q0..q1], &r_str[r0..r1]);
+        let (mut r, mut q) = (r0, q0);
+        for op in alignment.operations.iter() {
+            match op {
+                AlignmentOperation::Match | AlignmentOperation::Subst => {push_segment(&mut segments, (r, q, 1)); r += 1; q += 1;}
+                AlignmentOperation::Ins => q += 1,
+                AlignmentOperation::Del => r += 1,
+                _ => {}
+            }
+        }
+    }
+    segments
+}
+
+// Format a chain record (header and alignment data lines) for segments of a block, lifting the reference (target) over to the contig (query).
+// The chain ID is given by the caller, so that IDs are unique across contigs.
+pub fn chain_record(id: usize, r_name: &str, r_len: usize, q_name: &str, q_len: usize, rc: bool, segments: &[Segment]) -> String {
+    let (first, last) = (segments[0], segments[segments.len() - 1]);
+    let score = segments.iter().map(|s| s.2).sum::<usize>();
+    let mut record = format!("chain {} {} {} + {} {} {} {} {} {} {} {}\n", score, r_name, r_len, first.0, last.0 + last.2, q_name, q_len, if rc {"-"} else {"+"}, first.1, last.1 + last.2, id);
+    for w in segments.windows(2) {
+        record.push_str(&format!("{}\t{}\t{}\n", w[0].2, w[1].0 - (w[0].0 + w[0].2), w[1].1 - (w[0].1 + w[0].2)));
+    }
+    record.push_str(&format!("{}\n\n", last.2));
+    record
+}
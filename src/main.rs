@@ -11,7 +11,9 @@
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 use crate::index::{Entry, Index, ReadOnlyIndex};
+use crate::mask::Mask;
 use crate::stats::Stats;
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
 use std::mem::{MaybeUninit};
@@ -21,12 +23,33 @@ use flate2::read::GzDecoder;
 use lzzzz::lz4f::{BufReadDecompressor};
 use rust_seq2kminmers::{FH, KH};
 use structopt::StructOpt;
+mod asm;
+mod asm_qc;
 mod chain;
 mod closures;
+mod compare;
+mod coverage;
+mod dotplot;
+mod eval;
+mod gfa;
 mod index;
+mod inspect;
+mod liftover;
+mod mappability;
+mod mask;
 mod r#match;
 mod mers;
+mod overlap;
+mod paf;
+mod presets;
+mod report;
+mod scaffold;
+mod simulate;
+mod splice;
 mod stats;
+mod summary;
+mod sv;
+mod tune;
 
 pub type PseudoChainCoords = (bool, usize, usize, usize, usize, usize, usize);
 pub type PseudoChainCoordsTuple<'a> = (usize, PseudoChainCoords);
@@ -38,13 +61,58 @@ pub struct Params {
     use_simd: bool,
     use_pfx: bool,
     debug: bool,
+    read_stats: bool,
+    coverage_bin: usize, // coverage bin size (0: no coverage output)
+    coverage_min_mapq: usize, // minimum MAPQ of mappings counted in coverage
+    dotplot: HashSet<String>, // reads whose matches are plotted
     a: bool,
     c: usize, // minimum chain length
     s: usize, // minimum match score (# of matching seeds)
     g: usize, // maximum gap difference
+    max_indel: usize, // maximum gap difference of a large indel within a chain (0: large indels split mappings)
+    indel_penalty: usize, // base cost of a large indel chained over, in k-min-mer matches (plus log2 of its length)
+    inversions: bool, // report inverted segments within mappings
+    splice: bool, // spliced mapping (cDNA reads)
+    min_intron: usize, // minimum intron length in spliced mode
+    max_intron: usize, // maximum intron length in spliced mode
+    gtf: Option<PathBuf>, // annotation whose junctions are favoured in spliced mode
+    haplotypes: bool, // reads are assigned to a haplotype of the reference (see Mask::load_haplotypes)
     b: usize, // buffer increase
     q: usize, // queue length
 }
+impl Params {
+
+    // Params for the given seeding parameters, with default chaining parameters and no optional outputs (used by subcommands).
+    pub fn seeding(k: usize, l: usize, density: FH, use_hpc: bool) -> Self {
+        Params {
+            k,
+            l,
+            density,
+            use_hpc,
+            use_simd: std::is_x86_feature_detected!("avx512f"),
+            use_pfx: false,
+            debug: false,
+            read_stats: false,
+            coverage_bin: 0,
+            coverage_min_mapq: 0,
+            dotplot: HashSet::new(),
+            a: false,
+            c: 4,
+            s: 11,
+            g: 2000,
+            max_indel: 0,
+            indel_penalty: 3,
+            inversions: false,
+            splice: false,
+            min_intron: 50,
+            max_intron: 200000,
+            gtf: None,
+            haplotypes: false,
+            b: 1,
+            q: 200,
+        }
+    }
+}
 
 /// Try to get memory usage (resident set size) in bytes using the `getrusage()` function from libc.
 // from https://github.com/digama0/mm0/blob/bebd670c5a77a1400913ebddec2c6248e76f90fe/mm0-rs/src/util.rs
@@ -57,6 +125,18 @@ fn get_memory_rusage() -> usize {
     usage.ru_maxrss as usize * 1024
 }
 
+// Check if a file is FASTA (rather than FASTQ) from its name, possibly compressed.
+fn is_fasta_filename(path: &PathBuf) -> bool {
+    let filename_str = path.to_str().unwrap();
+    filename_str.contains(".fasta.") || filename_str.ends_with(".fna") || filename_str.contains(".fna.") || filename_str.contains(".fa.") || filename_str.ends_with(".fa") || filename_str.ends_with(".fasta") // not so robust but will have to do for now
+}
+
+// Check if a reference is a GFA graph from its name, possibly compressed.
+fn is_gfa_filename(path: &PathBuf) -> bool {
+    let filename_str = path.to_str().unwrap();
+    filename_str.ends_with(".gfa") || filename_str.contains(".gfa.")
+}
+
 fn get_reader(path: &PathBuf) -> (Box<dyn BufRead + Send>, bool) {
     let mut filetype = "unzip";
     let filename_str = path.to_str().unwrap();
@@ -74,6 +154,77 @@ fn get_reader(path: &PathBuf) -> (Box<dyn BufRead + Send>, bool) {
     reader
 }
 
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Map assembly contigs to a reference as synteny blocks
+    ///
+    /// Each contig is reported as all its significant colinear
+    /// blocks (on any reference and strand) in PAF, with an
+    /// identity estimate in the dv:f tag.
+    Asm(asm::AsmOpt),
+    /// Find suspected misassemblies from read mappings
+    ///
+    /// Reads are mapped to the assembly, and positions where
+    /// many reads' mappings break (clipped ends or split
+    /// segments) and coverage drops are written as BED.
+    AsmQc(asm_qc::AsmQcOpt),
+    /// Compare two sets of mappings of the same reads
+    ///
+    /// Reads are classified as concordant, discordant on the
+    /// same reference, discordant on different references, or
+    /// mapped in one set only.
+    Compare(compare::CompareOpt),
+    /// Evaluate the mapping accuracy of simulated reads
+    ///
+    /// True positions are recovered from read names
+    /// (<name>!<chrom>!<start>!<end>!<strand>, as written by
+    /// pbsim/paftools pbsim2fq).
+    Eval(eval::EvalOpt),
+    /// Print the k-min-mers, matches and chains of one sequence
+    ///
+    /// Shows, for a read or reference region, each k-min-mer
+    /// and the index entry it hits (unique, repeated or
+    /// absent), the resulting matches per reference, the
+    /// chains and the final placement.
+    Inspect(inspect::InspectOpt),
+    /// Write a track of unique k-min-mer density along a reference
+    ///
+    /// Repeated k-min-mers are not used as seeds, so regions
+    /// without unique k-min-mers cannot be mapped to.
+    Mappability(mappability::MappabilityOpt),
+    /// Find all-vs-all overlaps between reads
+    ///
+    /// Reads are indexed with all occurrences of their
+    /// k-min-mers, and each pair of overlapping reads is
+    /// reported once in PAF, with its overlap type
+    /// (dovetail or containment) in the ov:Z tag.
+    Overlap(overlap::OverlapOpt),
+    /// Order and orient draft contigs along a reference
+    ///
+    /// Writes an AGP file and a scaffolded FASTA, with gap
+    /// sizes estimated from reference coordinates, and lists
+    /// contigs with conflicting placements.
+    Scaffold(scaffold::ScaffoldOpt),
+    /// Simulate HiFi-like reads from a reference
+    ///
+    /// True positions are encoded in read names, for use
+    /// with the eval subcommand.
+    Simulate(simulate::SimulateOpt),
+    /// Call structural variant candidates from split reads
+    ///
+    /// Breakpoints between read segments that cannot be
+    /// chained are clustered across reads and written as a
+    /// VCF of deletions, insertions, inversions, duplications
+    /// and translocations (breakends).
+    Sv(sv::SvOpt),
+    /// Find the best (k, l, density) for a reference and read sample
+    ///
+    /// Maps the sample with each setting of a parameter grid,
+    /// evaluates the mappings and recommends the setting with
+    /// the most reads at MAPQ 60 within a time/memory budget.
+    Tune(tune::TuneOpt),
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "mapquik")]
 /// Original implementation of mapquik, a fast HiFi read mapper.
@@ -93,6 +244,14 @@ struct Opt {
     /// 
     #[structopt(parse(from_os_str), short, long)]
     prefix: Option<PathBuf>,
+    /// Parameter preset (hifi, duplex, ont-r10-q20, asm5, asm20)
+    ///
+    /// Sets k, l, density, chain length, seed count, gap
+    /// difference and hashing mode together for a read
+    /// technology or assembly divergence. Individual flags
+    /// override the preset.
+    #[structopt(long)]
+    preset: Option<String>,
     /// k-min-mer length
     ///
     /// The length of each k-min-mer. If
@@ -128,6 +287,51 @@ struct Opt {
     /// Allows chaining of hits with a gap difference of < g.
     #[structopt(short, long)]
     gap_diff: Option<usize>,
+    /// Maximum length of a large indel within a mapping
+    ///
+    /// Blocks of matches separated from the chain by a gap
+    /// difference above g but at most this are chained across
+    /// a large deletion or insertion (reported in the li:Z
+    /// tag) instead of being dropped. 0 disables this.
+    #[structopt(long, default_value = "0")]
+    max_indel: usize,
+    /// Penalty of a large indel, in k-min-mer matches
+    ///
+    /// Each large indel costs this plus log2 of its length,
+    /// subtracted from the chain score (MAPQ is lowered in
+    /// proportion). A block is only chained across a large
+    /// indel if it has more k-min-mer matches than its cost.
+    #[structopt(long, default_value = "3")]
+    indel_penalty: usize,
+    /// Report inverted segments within mappings
+    ///
+    /// Opposite-strand matches lying between two consecutive
+    /// matches of a mapping, on both the read and the
+    /// reference (a forward-reverse-forward pattern), are
+    /// reported as a supplementary record with breakpoints.
+    #[structopt(long)]
+    inversions: bool,
+    /// Spliced mapping of full-length cDNA reads (e.g. Iso-Seq)
+    ///
+    /// Chains allow reference-only gaps (introns), junctions
+    /// are refined to GT-AG motifs against the reference
+    /// (which is kept in memory), and mappings get a CIGAR
+    /// with N operations (cg:Z tag) and are also written as
+    /// BED12 transcript models to <prefix>.splice.bed.
+    #[structopt(long)]
+    splice: bool,
+    /// Minimum intron length in spliced mode
+    ///
+    #[structopt(long, default_value = "50")]
+    min_intron: usize,
+    /// Maximum intron length in spliced mode
+    ///
+    #[structopt(long, default_value = "200000")]
+    max_intron: usize,
+    /// Annotation (GTF) whose splice junctions are favoured in spliced mode
+    ///
+    #[structopt(parse(from_os_str), long)]
+    gtf: Option<PathBuf>,
     /// Reference genome input
     ///
     /// Reference to be indexed and mapped to. 
@@ -135,6 +339,42 @@ struct Opt {
     /// doesn't filter any kminmers.
     #[structopt(parse(from_os_str), long)]
     reference: Option<PathBuf>,
+    /// BED file of reference regions to exclude from indexing
+    ///
+    /// K-min-mers overlapping any of these regions
+    /// (e.g. centromeric satellites, known false
+    /// duplications) are not indexed.
+    #[structopt(parse(from_os_str), long)]
+    mask: Option<PathBuf>,
+    /// File of decoy reference names (one per line)
+    ///
+    /// Decoy references are indexed and compete for
+    /// reads, but placements on them are not reported.
+    #[structopt(parse(from_os_str), long)]
+    decoys: Option<PathBuf>,
+    /// Comma-separated names of circular references
+    ///
+    /// K-min-mers and mappings of circular references
+    /// (plasmids, mitochondria, bacterial chromosomes)
+    /// wrap around the origin; mappings spanning it are
+    /// split into a primary and a supplementary (tp:A:S)
+    /// record. References whose FASTA
+    /// header contains "circular=true" or
+    /// "topology=circular" are also treated as circular.
+    #[structopt(long)]
+    circular: Option<String>,
+    /// Haplotype of each reference of a diploid assembly (TSV: name, 1 or 2)
+    ///
+    /// K-min-mers occurring once on each haplotype are
+    /// kept in the index at both positions instead of
+    /// being discarded as repeats. Reads are assigned to
+    /// the haplotype with more haplotype-specific
+    /// k-min-mers and placed on it (MAPQ 0 if the other
+    /// haplotype has as good a chain), and tagged with
+    /// hp:i (haplotype, 0 if unassigned), h1:i and h2:i
+    /// (specific k-min-mers of each).
+    #[structopt(parse(from_os_str), long)]
+    haplotypes: Option<PathBuf>,
     /// Number of threads
     /// 
     #[structopt(long)]
@@ -147,12 +387,43 @@ struct Opt {
     /// 
     #[structopt(long)]
     low_memory: bool,
+    /// Write per-read seeding and chaining statistics
+    ///
+    /// Records, for each read, the number of k-min-mers,
+    /// index hits, repeated hits, candidate references,
+    /// matches, chains and distinct reference loci to
+    /// <prefix>.read_stats.tsv.
+    #[structopt(long)]
+    read_stats: bool,
+    /// Write per-reference coverage depth in bins of this size
+    ///
+    /// Accumulates the reference span of each reported
+    /// mapping (without large deletions, nor introns in
+    /// --splice mode) in fixed-size bins and writes the
+    /// mean depth per bin to <prefix>.coverage.bedgraph.
+    #[structopt(long)]
+    coverage: Option<usize>,
+    /// Minimum MAPQ of mappings counted in coverage
+    ///
+    #[structopt(long)]
+    coverage_min_mapq: Option<usize>,
+    /// Comma-separated read IDs to draw a dot plot for
+    ///
+    /// Writes, for each of these reads, an SVG dot plot of
+    /// its matches (query vs. reference coordinates, by
+    /// strand, and whether they were kept by chaining) to
+    /// <prefix>.<read>.dotplot.svg.
+    #[structopt(long)]
+    dotplot: Option<String>,
     /// Deactivate SIMD (AVX2,AVX512) functions (for old processors)
     #[structopt(long)]
     nosimd: bool,
     /// Deactivate HomoPolymer Compression
     #[structopt(long)]
     nohpc: bool,
+    /// Activate HomoPolymer Compression (overrides the preset)
+    #[structopt(long, conflicts_with = "nohpc")]
+    hpc: bool,
     /// Use parallelfastx (faster uncompressed reads parsing)
     #[structopt(long)]
     parallelfastx: bool,
@@ -162,12 +433,30 @@ struct Opt {
     /// queue length
     #[structopt(short, long)]
     q: Option<usize>,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 
 }
 
 fn main() {
     let start = Instant::now();
     let opt = Opt::from_args();      
+    if let Some(cmd) = &opt.cmd {
+        match cmd {
+            Command::Asm(asm_opt) => asm::run(asm_opt),
+            Command::AsmQc(asm_qc_opt) => asm_qc::run(asm_qc_opt),
+            Command::Compare(compare_opt) => compare::run(compare_opt),
+            Command::Eval(eval_opt) => eval::run(eval_opt),
+            Command::Inspect(inspect_opt) => inspect::run(inspect_opt),
+            Command::Mappability(mappability_opt) => mappability::run(mappability_opt),
+            Command::Overlap(overlap_opt) => overlap::run(overlap_opt),
+            Command::Scaffold(scaffold_opt) => scaffold::run(scaffold_opt),
+            Command::Simulate(simulate_opt) => simulate::run(simulate_opt),
+            Command::Sv(sv_opt) => sv::run(sv_opt),
+            Command::Tune(tune_opt) => tune::run(tune_opt),
+        }
+        return;
+    }
     let mut filename = PathBuf::new();
     let mut ref_filename = PathBuf::new();
     let mut output_prefix;
@@ -193,30 +482,51 @@ fn main() {
     let mut reads_are_fasta : bool = false;
     let mut ref_is_fasta    : bool = false;
     let filename_str = filename.to_str().unwrap();
-    if filename_str.contains(".fasta.") || filename_str.ends_with(".fna") || filename_str.contains(".fna.") || filename_str.contains(".fa.") || filename_str.ends_with(".fa") || filename_str.ends_with(".fasta") { // not so robust but will have to do for now
+    if is_fasta_filename(&filename) {
         reads_are_fasta = true;
         println!("Input file: {}", filename_str);
         println!("Format: FASTA");
     }
     let ref_filename_str = ref_filename.to_str().unwrap();
-    if ref_filename_str.contains(".fasta.") || ref_filename_str.ends_with(".fna") || ref_filename_str.contains(".fna.") || ref_filename_str.contains(".fa.") || ref_filename_str.ends_with(".fa") || ref_filename_str.ends_with(".fasta") { // not so robust but will have to do for now
+    if is_fasta_filename(&ref_filename) {
         ref_is_fasta = true;
         println!("Reference file: {}", ref_filename_str);
         println!("Format: FASTA");
     }
-    if opt.k.is_some() {k = opt.k.unwrap()} else {println!("Warning: Using default k value ({}).", k);} 
-    if opt.l.is_some() {l = opt.l.unwrap()} else {println!("Warning: Using default l value ({}).", l);}
+    else if is_gfa_filename(&ref_filename) {
+        println!("Reference file: {}", ref_filename_str);
+        println!("Format: GFA");
+    }
+    let preset = opt.preset.as_ref().map(|name| presets::get(name));
+    if let Some(p) = preset {
+        k = p.k;
+        l = p.l;
+        density = p.density;
+        c = p.c;
+        s = p.s;
+        g = p.g;
+        use_hpc = p.use_hpc;
+        println!("Using preset {}.", p.name);
+    }
+    if opt.k.is_some() {k = opt.k.unwrap()} else if preset.is_none() {println!("Warning: Using default k value ({}).", k);} 
+    if opt.l.is_some() {l = opt.l.unwrap()} else if preset.is_none() {println!("Warning: Using default l value ({}).", l);}
     if opt.b.is_some() {b = opt.b.unwrap()} else {println!("Warning: Using default buffer size ({}X).", b);}
     if opt.q.is_some() {q = opt.q.unwrap()} else {println!("Warning: Using default queue length ({}).", q);}
-    if opt.density.is_some() {density = opt.density.unwrap()} else {println!("Warning: Using default density value ({}%).", density * 100.0);}
+    if opt.density.is_some() {density = opt.density.unwrap()} else if preset.is_none() {println!("Warning: Using default density value ({}%).", density * 100.0);}
     if opt.threads.is_some() {threads = opt.threads.unwrap();} else {println!("Warning: Using default number of threads (8).");}
-    if opt.chain.is_some() {c = opt.chain.unwrap()} else {println!("Warning: Using default minimum chain length ({}).", c);}
-    if opt.seed.is_some() {s = opt.seed.unwrap()} else {println!("Warning: Using default minimum number of matching seeds ({}).", s);}
-    if opt.gap_diff.is_some() {g = opt.gap_diff.unwrap()} else {println!("Warning: Using default maximum seed gap difference ({}).", g);}
+    if opt.chain.is_some() {c = opt.chain.unwrap()} else if preset.is_none() {println!("Warning: Using default minimum chain length ({}).", c);}
+    if opt.seed.is_some() {s = opt.seed.unwrap()} else if preset.is_none() {println!("Warning: Using default minimum number of matching seeds ({}).", s);}
+    if opt.gap_diff.is_some() {g = opt.gap_diff.unwrap()} else if preset.is_none() {println!("Warning: Using default maximum seed gap difference ({}).", g);}
     output_prefix = PathBuf::from(format!("mapquik-k{}-d{}-l{}", k, density, l));
     if opt.prefix.is_some() {output_prefix = opt.prefix.unwrap();} else {println!("Warning: Using default output prefix ({}).", output_prefix.to_str().unwrap());}
     let debug = opt.debug;
+    let read_stats = opt.read_stats;
+    let coverage_bin = opt.coverage.unwrap_or(0);
+    let coverage_min_mapq = opt.coverage_min_mapq.unwrap_or(0);
+    let mut dotplot = HashSet::<String>::new();
+    if let Some(ids) = opt.dotplot {dotplot = ids.split(',').filter(|n| !n.is_empty()).map(|n| n.to_string()).collect();}
     if opt.nohpc  { use_hpc = false; }
+    if opt.hpc    { use_hpc = true; }
     if opt.nosimd { use_simd = false; }
     if opt.parallelfastx { use_pfx = true; }
     if ! std::is_x86_feature_detected!("avx512f") { 
@@ -238,6 +548,8 @@ fn main() {
             println!("Using regular ntHash (not HPC), scalar");
         }
     }
+    println!("Effective configuration: preset={}, k={}, l={}, density={}, chain={}, seeds={}, gap_diff={}, max_indel={}, hpc={}, simd={}, threads={}",
+             preset.map_or("none", |p| p.name), k, l, density, c, s, g, opt.max_indel, use_hpc, use_simd, threads);
     let params = Params { 
         k,
         l,
@@ -246,10 +558,22 @@ fn main() {
         use_simd,
         use_pfx,
         debug,
+        read_stats,
+        coverage_bin,
+        coverage_min_mapq,
+        dotplot,
         a,
         c,
         s,
         g,
+        max_indel: opt.max_indel,
+        indel_penalty: opt.indel_penalty,
+        inversions: opt.inversions,
+        splice: opt.splice,
+        min_intron: opt.min_intron,
+        max_intron: opt.max_intron,
+        gtf: opt.gtf,
+        haplotypes: opt.haplotypes.is_some(),
         b,
         q,
     };
@@ -257,14 +581,25 @@ fn main() {
     // get file size for progress bar
     let _metadata = fs::metadata(&filename).expect("Error opening input file.");
     let _ref_metadata = fs::metadata(&ref_filename).expect("Error opening reference file.");
+    let mut mask = Mask::new();
+    if let Some(mask_filename) = opt.mask {mask.load_bed(&mask_filename);}
+    if let Some(decoys_filename) = opt.decoys {mask.load_decoys(&decoys_filename);}
+    let (nb_masked_bases, nb_decoys) = mask.get_counts();
+    if nb_masked_bases > 0 {println!("Masking {} reference bases from indexing.", nb_masked_bases);}
+    if nb_decoys > 0 {println!("Using {} decoy references.", nb_decoys);}
+    if let Some(haplotypes_filename) = opt.haplotypes {
+        mask.load_haplotypes(&haplotypes_filename);
+        println!("Using haplotypes of {} references.", mask.nb_haplotype_refs());
+    }
+    let mut circular_names = HashSet::<String>::new();
+    if let Some(names) = opt.circular {circular_names = names.split(',').filter(|n| !n.is_empty()).map(|n| n.to_string()).collect();}
     let ref_threads = threads;
     let mut ref_queue_len = threads;
     if low_memory {ref_queue_len = 1;}
     let queue_len = params.q; // https://doc.rust-lang.org/std/sync/mpsc/fn.sync_channel.html
                              // also: controls how many reads objects are buffered during fasta/fastq
                              // parsing
-    Stats::init(threads, output_prefix.to_str().unwrap());
-    closures::run_mers(&filename, &ref_filename, &params, ref_threads, threads, ref_queue_len, queue_len, reads_are_fasta, ref_is_fasta, &output_prefix);
+    closures::run_mers(&filename, &ref_filename, &params, ref_threads, threads, ref_queue_len, queue_len, reads_are_fasta, ref_is_fasta, &output_prefix, &mask, &circular_names);
     //println!("current time after exiting closures {:?}",Utc::now());
     let duration = start.elapsed();
     println!("Total execution time: {:?}", duration);
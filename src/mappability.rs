@@ -0,0 +1,102 @@
+// mappability.rs
+// Contains the "mappability" subcommand, which indexes a reference and reports the density of unique k-min-mers along it
+// (regions without unique k-min-mers cannot be mapped to, as repeated k-min-mers are blanked in the Index).
+
+use crate::{Params, closures, index::ReadOnlyIndex, is_fasta_filename, mask::Mask, mers, presets::SeedingOpt, summary::Summary};
+use rust_seq2kminmers::Kminmer;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct MappabilityOpt {
+    /// Reference genome input
+    ///
+    #[structopt(parse(from_os_str))]
+    pub reference: PathBuf,
+    /// Output prefix
+    ///
+    /// Unique k-min-mer density is written to
+    /// <prefix>.mappability.bedgraph, and low-mappability
+    /// regions to <prefix>.low_mappability.bed.
+    #[structopt(short, long, default_value = "mapquik")]
+    pub prefix: String,
+    /// Window size
+    ///
+    #[structopt(short, long, default_value = "10000")]
+    pub window: usize,
+    /// Minimum unique k-min-mers per kb
+    ///
+    /// Windows with a lower density of unique
+    /// k-min-mers are reported as low-mappability.
+    #[structopt(long, default_value = "1")]
+    pub min_density: f64,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+}
+
+// Count the unique and repeated k-min-mers starting in each window of a reference sequence.
+pub fn count_windows(seq: &[u8], params: &Params, index: &ReadOnlyIndex, window: usize) -> Vec<(usize, usize)> {
+    let mut counts = vec![(0, 0); (seq.len() + window - 1) / window];
+    if let Some(iter) = mers::extract("", seq, params) {
+        for kminmer in iter {
+            let w = &mut counts[kminmer.start / window];
+            match index.get_raw(&kminmer.get_hash()) {
+                Some(e) if e.is_empty() => w.1 += 1,
+                Some(_) => w.0 += 1,
+                None => {} // masked
+            }
+        }
+    }
+    counts
+}
+
+// Run the mappability subcommand.
+pub fn run(opt: &MappabilityOpt) {
+    let params = opt.seeding.to_params("hifi");
+    let threads = opt.seeding.preset.threads;
+    if opt.window == 0 {panic!("Window size must be > 0.");}
+    let ref_is_fasta = is_fasta_filename(&opt.reference);
+    let mut summary = Summary::new();
+    let (index, _ref_map) = closures::build_index(&opt.reference, &params, threads, threads, ref_is_fasta, &Mask::new(), &HashSet::new(), &mut summary);
+    let bedgraph_path = format!("{}.mappability.bedgraph", opt.prefix);
+    let bed_path = format!("{}.low_mappability.bed", opt.prefix);
+    let mut bedgraph = match File::create(&bedgraph_path) {
+        Err(why) => panic!("Couldn't create {}: {}", bedgraph_path, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let mut bed = match File::create(&bed_path) {
+        Err(why) => panic!("Couldn't create {}: {}", bed_path, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let (mut nb_windows, mut nb_low, mut low_bases, mut nb_repeated) = (0, 0, 0, 0);
+    closures::for_each_record(&opt.reference, ref_is_fasta, |id, _desc, seq| {
+        let counts = count_windows(seq, &params, &index, opt.window);
+        let mut low : Option<(usize, usize)> = None; // current run of low-mappability windows
+        for (w, (unique, repeated)) in counts.iter().enumerate() {
+            let start = w * opt.window;
+            let end = (start + opt.window).min(seq.len());
+            let density = *unique as f64 * 1000.0 / (end - start) as f64;
+            writeln!(bedgraph, "{}\t{}\t{}\t{:.3}", id, start, end, density).expect("Error writing mappability line.");
+            nb_windows += 1;
+            nb_repeated += repeated;
+            if density < opt.min_density {
+                nb_low += 1;
+                low_bases += end - start;
+                low = match low {
+                    Some((s, _)) => Some((s, end)),
+                    None => Some((start, end)),
+                };
+            }
+            else if let Some((s, e)) = low.take() {
+                writeln!(bed, "{}\t{}\t{}", id, s, e).expect("Error writing low-mappability region.");
+            }
+        }
+        if let Some((s, e)) = low {writeln!(bed, "{}\t{}\t{}", id, s, e).expect("Error writing low-mappability region.");}
+    });
+    println!("{} reference k-min-mers are repeated and cannot be used as seeds.", nb_repeated);
+    println!("{} of {} windows ({} bases) have fewer than {} unique k-min-mers per kb.", nb_low, nb_windows, low_bases, opt.min_density);
+    println!("Wrote unique k-min-mer density to {} and low-mappability regions to {}.", bedgraph_path, bed_path);
+}
@@ -0,0 +1,112 @@
+// mask.rs
+// Contains the "Mask" struct, which holds reference regions excluded from indexing (from a BED file), the names of decoy references, and the haplotype of each reference of a diploid assembly.
+
+use crate::get_reader;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Default)]
+pub struct Mask {
+    regions: HashMap<String, Vec<(usize, usize)>>, // Sorted, merged half-open intervals per reference
+    decoys: HashSet<String>, // Names of decoy references
+    haplotypes: HashMap<String, u8>, // Haplotype bit (1 or 2) per reference
+}
+impl Mask {
+
+    // An empty Mask (nothing masked, no decoys).
+    pub fn new() -> Self {
+        Mask::default()
+    }
+
+    // Load masked regions from a BED file (only the first three columns are used).
+    pub fn load_bed(&mut self, path: &PathBuf) {
+        let (buf, _) = get_reader(path);
+        for line in buf.lines() {
+            let line = line.expect("Error reading mask BED file.");
+            if line.is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {continue;}
+            let fields : Vec<&str> = line.split('\t').collect();
+            if fields.len() < 3 {panic!("Malformed BED line in mask file: {}", line);}
+            let start = fields[1].parse::<usize>().expect("Invalid start coordinate in mask BED file.");
+            let end = fields[2].parse::<usize>().expect("Invalid end coordinate in mask BED file.");
+            if end > start {self.regions.entry(fields[0].to_string()).or_insert_with(Vec::new).push((start, end));}
+        }
+        for intervals in self.regions.values_mut() {
+            intervals.sort_unstable();
+            let mut merged : Vec<(usize, usize)> = Vec::with_capacity(intervals.len());
+            for &(s, e) in intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if s <= last.1 => {last.1 = last.1.max(e);}
+                    _ => merged.push((s, e)),
+                }
+            }
+            *intervals = merged;
+        }
+    }
+
+    // Load decoy reference names from a file (one name per line, first whitespace-separated field).
+    pub fn load_decoys(&mut self, path: &PathBuf) {
+        let (buf, _) = get_reader(path);
+        for line in buf.lines() {
+            let line = line.expect("Error reading decoy list.");
+            if let Some(name) = line.split_whitespace().next() {
+                let name = name.trim_start_matches('>');
+                if !name.is_empty() && !name.starts_with('#') {self.decoys.insert(name.to_string());}
+            }
+        }
+    }
+
+    // Load the haplotype of references from a TSV file (reference name, haplotype 1 or 2).
+    pub fn load_haplotypes(&mut self, path: &PathBuf) {
+        let (buf, _) = get_reader(path);
+        for line in buf.lines() {
+            let line = line.expect("Error reading haplotypes file.");
+            if line.is_empty() || line.starts_with('#') {continue;}
+            let fields : Vec<&str> = line.split('\t').collect();
+            if fields.len() < 2 {panic!("Malformed line in haplotypes file: {}", line);}
+            let bit = match fields[1].trim() {
+                "1" => 1,
+                "2" => 2,
+                h => panic!("Invalid haplotype {} in haplotypes file (expected 1 or 2).", h),
+            };
+            self.haplotypes.insert(fields[0].to_string(), bit);
+        }
+    }
+
+    // Get the haplotype bit of a reference (0 if none).
+    pub fn haplotype(&self, ref_id: &str) -> u8 {
+        *self.haplotypes.get(ref_id).unwrap_or(&0)
+    }
+
+    // Number of references with a haplotype.
+    pub fn nb_haplotype_refs(&self) -> usize {
+        self.haplotypes.len()
+    }
+
+    // Get the masked intervals of a reference (empty if none).
+    pub fn get_regions(&self, ref_id: &str) -> &[(usize, usize)] {
+        match self.regions.get(ref_id) {
+            Some(v) => v,
+            None => &[],
+        }
+    }
+
+    // Check if a reference is a decoy.
+    pub fn is_decoy(&self, ref_id: &str) -> bool {
+        self.decoys.contains(ref_id)
+    }
+
+    // Total number of masked bases and number of decoy references.
+    pub fn get_counts(&self) -> (usize, usize) {
+        let masked = self.regions.values().flatten().map(|(s, e)| e - s).sum::<usize>();
+        (masked, self.decoys.len())
+    }
+}
+
+// Check if the half-open interval [start, end) overlaps any of the sorted, merged intervals.
+pub fn overlaps(regions: &[(usize, usize)], start: usize, end: usize) -> bool {
+    if regions.is_empty() {return false;}
+    // index of the first interval whose end is > start
+    let i = regions.partition_point(|&(_, e)| e <= start);
+    i < regions.len() && regions[i].0 < end
+}
@@ -1,7 +1,7 @@
 // match.rs
 // Contains the "Match" struct, which represents a collection of consecutive k-min-mer matches from query to reference.
 
-use crate::{Entry, ReadOnlyIndex};
+use crate::{Entry, ReadOnlyIndex, Stats};
 use std::{fmt, iter::Peekable};
 use rust_seq2kminmers::{KminmersIterator, KminmerType as Kminmer, Kminmer as KminmerTrait};
 
@@ -41,20 +41,29 @@ impl Match {
         (self.rc && (p.offset as i32 - r.offset as i32 == 1)) || 
         (!self.rc && (r.offset as i32 - p.offset as i32 == 1))
     }
-    // Extend this Match if it can be extended by the next Kminmer match.
-    pub fn extend(&mut self, query_it: &mut Peekable<&mut KminmersIterator>, index: &ReadOnlyIndex, p: &Entry) {
-        if let Some(q) = query_it.peek() {
-            let re = index.get(&q.get_hash());
-            if let Some(r) = re {
-                if self.check(q, r, p) {
+    // Extend this Match while it can be extended by the next Kminmer match (iteratively, as runs of consecutive matches can be very long on assemblies).
+    // Consumed k-min-mers are counted in stats.
+    pub fn extend<'a>(&mut self, query_it: &mut Peekable<&mut KminmersIterator>, index: &'a ReadOnlyIndex, p: &'a Entry, stats: &mut Stats) {
+        let mut p = p;
+        while let Some(q) = query_it.peek() {
+            let re = index.get_raw(&q.get_hash());
+            match re {
+                Some(r) if !r.is_empty() => {
+                    if !self.check(q, r, p) {return;}
                     self.update(q, r);
                     query_it.next();
-                    self.extend(query_it, index, r)
+                    stats.add_kminmer();
+                    stats.add_lookup(re);
+                    p = r;
+                }
+                _ => {
+                    query_it.next();
+                    stats.add_kminmer();
+                    stats.add_lookup(re);
+                    return;
                 }
             }
-            else {query_it.next();}
         }
-        else {query_it.next();}
     }
 }
 
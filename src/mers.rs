@@ -1,7 +1,7 @@
 // mers.rs
 // Contains the "Match", "Offset", and "AlignCand" types, along with driver functions for obtaining reference and query k-min-mers, Matches, Chains, and final coordinates.
 
-use crate::{r#match::Match, Index, ReadOnlyIndex, Params, Stats, PseudoChainCoordsTuple, chain::Chain};
+use crate::{r#match::Match, Entry, gfa::Graph, Index, ReadOnlyIndex, Params, Stats, PseudoChainCoords, PseudoChainCoordsTuple, chain::{Chain, Indel, Inversion}, index::RefInfo, mask, stats::Status};
 use std::collections::HashMap;
 use dashmap::DashMap;
 use rust_seq2kminmers::{KminmersIterator, FH, HashMode, Kminmer};
@@ -12,12 +12,23 @@ use rust_seq2kminmers::{KminmersIterator, FH, HashMode, Kminmer};
 //pub type AlignCand = (Offset, String, Offset, bool);
 
 // Extract k-min-mers from reference. We don't store k-min-mer objects or hashes in a Vec, but rather immediately insert into the Index.
-pub fn ref_extract(ref_idx: usize, inp_seq_raw: &[u8], params: &Params, mers_index: &Index) -> usize {
+// K-min-mers overlapping a masked interval are skipped. Returns the number of indexed and masked k-min-mers.
+// Entries carry the haplotype bit hap of the reference (0 if none), so that k-min-mers shared by haplotypes are kept (see Index::insert).
+// For circular references, the start of the sequence is appended to its end so that k-min-mers spanning the origin are indexed
+// (with end coordinates beyond the reference length); k-min-mers starting in the appended part are duplicates and skipped.
+pub fn ref_extract(ref_idx: usize, inp_seq_raw: &[u8], params: &Params, mers_index: &Index, masked: &[(usize, usize)], circular: bool, hap: u8) -> (usize, usize) {
     let l = params.l;
     let k = params.k;
     if inp_seq_raw.len() < l+k-1 {
-        return 0;
+        return (0, 0);
     }
+    let r_len = inp_seq_raw.len();
+    let extended_seq;
+    let inp_seq_raw = if circular {
+        let overhang = circular_overhang(params).min(r_len);
+        extended_seq = [inp_seq_raw, &inp_seq_raw[..overhang]].concat();
+        &extended_seq[..]
+    } else {inp_seq_raw};
     let density : FH = params.density;
     let mode = if params.use_simd {
         if params.use_hpc {HashMode::HpcSimd} else {HashMode::Simd}
@@ -26,15 +37,50 @@ pub fn ref_extract(ref_idx: usize, inp_seq_raw: &[u8], params: &Params, mers_ind
     };
     let iter = KminmersIterator::new(inp_seq_raw, l, k, density, mode).unwrap();
     let mut count = 0;
+    let mut nb_masked = 0;
     for kminmer in iter {
         //println!("{:?}", kminmer);
+        if kminmer.start >= r_len {break;}
+        if mask::overlaps(masked, kminmer.start, kminmer.end.min(r_len)) || (kminmer.end > r_len && mask::overlaps(masked, 0, kminmer.end - r_len)) {
+            nb_masked += 1;
+            continue;
+        }
         // Add a reference k-min-mer to the Index.
         //mers_index.add(kminmer.get_hash(), ref_idx, kminmer.start, kminmer.end, kminmer.offset, kminmer.rev);
-        mers_index.add_with_mer(ref_idx, &kminmer);
+        mers_index.add_with_mer(ref_idx, &kminmer, hap);
         count += 1;
         //eprintln!("{}\r", count);
     }
-    count
+    (count, nb_masked)
+}
+
+// Number of bases appended to a circular reference so that k-min-mers spanning the origin are fully contained (a few times the expected k-min-mer span).
+pub fn circular_overhang(params: &Params) -> usize {
+    4 * ((params.k as FH / params.density) as usize + params.l)
+}
+
+// Shift the Matches of a circular reference by the reference length so that they are colinear with the largest Match across the origin.
+// Resulting coordinates are in [0, 2*r_len).
+pub fn unwrap_circular(matches: &mut [Match], r_len: usize) {
+    if matches.len() <= 1 {return;}
+    let r_len = r_len as i64;
+    let diag = |h: &Match| -> i64 {
+        if h.rc {h.r_start as i64 + h.q_end as i64} else {h.r_start as i64 - h.q_start as i64}
+    };
+    let anchor = (0..matches.len()).max_by_key(|&i| matches[i].count).unwrap();
+    let anchor_diag = diag(&matches[anchor]);
+    let mut shifts = vec![0i64; matches.len()];
+    for (i, h) in matches.iter().enumerate() {
+        let d = diag(h) - anchor_diag;
+        if d > r_len / 2 {shifts[i] = -r_len;}
+        else if d < -r_len / 2 {shifts[i] = r_len;}
+    }
+    let min_start = matches.iter().zip(shifts.iter()).map(|(h, s)| h.r_start as i64 + s).min().unwrap();
+    let base = if min_start < 0 {r_len} else {0};
+    for (h, s) in matches.iter_mut().zip(shifts.iter()) {
+        h.r_start = (h.r_start as i64 + s + base) as usize;
+        h.r_end = (h.r_end as i64 + s + base) as usize;
+    }
 }
 
 // Extract k-min-mers from the query. We need to store Kminmer objects for the query in order to compute Matches.
@@ -54,42 +100,130 @@ pub fn extract<'a>(_seq_id: &str, inp_seq_raw: &'a [u8], params: &Params) -> Opt
 }
 
 // Generates raw Vecs of Matches by matching query k-min-mers to Entries from the Index.
-pub fn chain_matches(query_id: &str, query_it_raw: &mut Option<KminmersIterator>, index: &ReadOnlyIndex) -> HashMap<usize, Vec<Match>> {
+// K-min-mers with several loci are matched to all of them (see chain_matches_alt).
+pub fn chain_matches(query_id: &str, query_it_raw: &mut Option<KminmersIterator>, index: &ReadOnlyIndex, stats: &mut Stats) -> HashMap<usize, Vec<Match>> {
+    if index.has_alt() {return chain_matches_alt(query_it_raw, index, stats);}
     let mut matches_per_ref = HashMap::<usize, Vec<Match>>::new();
     if query_it_raw.is_none() {return matches_per_ref;}
-    //let mut stats = Stats::new(query_id);
     let mut query_it = query_it_raw.as_mut().unwrap().peekable();
     while let Some(q) = query_it.next() {
-        let re = index.get(&q.get_hash());
-        if let Some(r) = re {
+        let re = index.get_raw(&q.get_hash());
+        stats.add_kminmer();
+        stats.add_lookup(re);
+        if let Some(r) = re.filter(|r| !r.is_empty()) {
             let mut h = Match::new(&q, r);
-            h.extend(&mut query_it, index, r);
-            //stats.add(&r);
+            h.extend(&mut query_it, index, r, stats);
             matches_per_ref.entry(r.id).or_insert(Vec::new()).push(h);
         }
     }
-    //stats.finalize();
+    stats.nb_refs = matches_per_ref.len();
+    stats.nb_matches = matches_per_ref.values().map(|v| v.len()).sum();
+    matches_per_ref
+}
+
+// Same as chain_matches, for an Index where k-min-mers may have several loci (see Index::alt): each query k-min-mer matches all of them,
+// and a Match is open on every reference it hits, extended by the next query k-min-mer if it hits the same reference at the next offset.
+pub fn chain_matches_alt(query_it_raw: &mut Option<KminmersIterator>, index: &ReadOnlyIndex, stats: &mut Stats) -> HashMap<usize, Vec<Match>> {
+    let mut matches_per_ref = HashMap::<usize, Vec<Match>>::new();
+    if query_it_raw.is_none() {return matches_per_ref;}
+    let mut open = HashMap::<usize, (Match, &Entry)>::new(); // Match being extended and its last Entry, per reference
+    for q in query_it_raw.as_mut().unwrap() {
+        let h = q.get_hash();
+        let re = index.get_raw(&h);
+        stats.add_kminmer();
+        stats.add_lookup(re);
+        let mut next = HashMap::<usize, (Match, &Entry)>::new();
+        if let Some(r) = re.filter(|r| !r.is_empty()) {
+            for r in std::iter::once(r).chain(index.get_alt(&h).iter()) {
+                if next.contains_key(&r.id) {continue;}
+                let h = match open.remove(&r.id) {
+                    Some((mut h, p)) if h.check(&q, r, p) => {h.update(&q, r); h}
+                    Some((h, _)) => {
+                        matches_per_ref.entry(r.id).or_insert(Vec::new()).push(h);
+                        Match::new(&q, r)
+                    }
+                    None => Match::new(&q, r),
+                };
+                next.insert(r.id, (h, r));
+            }
+        }
+        for (r_id, (h, _)) in open.drain() {matches_per_ref.entry(r_id).or_insert(Vec::new()).push(h);}
+        open = next;
+    }
+    for (r_id, (h, _)) in open.drain() {matches_per_ref.entry(r_id).or_insert(Vec::new()).push(h);}
+    stats.nb_refs = matches_per_ref.len();
+    stats.nb_matches = matches_per_ref.values().map(|v| v.len()).sum();
     matches_per_ref
 }
 
 
 // Extract raw Vecs of Matches, construct a Chain, and obtain a final Match (and populate alignment DashMaps with intervals if necessary).
-pub fn find_matches(q_id: &str, q_len: usize, q_str: &[u8], ref_map: &DashMap<usize, (String, usize)>, mers_index: &ReadOnlyIndex, params: &Params) /* aln_coords: &DashMap<String, Vec<AlignCand>>) */-> Option<String> {
+// Reads whose best placement is on a decoy reference are not reported. For GFA references, graph holds the paths of the references. Seeding and chaining statistics, and the mapping outcome, are recorded in stats.
+pub fn find_matches(q_id: &str, q_len: usize, q_str: &[u8], ref_map: &DashMap<usize, RefInfo>, mers_index: &ReadOnlyIndex, params: &Params, graph: Option<&Graph>, stats: &mut Stats) /* aln_coords: &DashMap<String, Vec<AlignCand>>) */-> Option<String> {
     let mut kminmers = extract(q_id, q_str, params);
-    let matches_per_ref = chain_matches(q_id, &mut kminmers, mers_index);
+    let mut matches_per_ref = chain_matches(q_id, &mut kminmers, mers_index, stats);
     let mut all_pseudocoords = Vec::<PseudoChainCoordsTuple>::new();    
-    for e in matches_per_ref.iter() {
+    let mut indels_per_ref = HashMap::<usize, Vec<Indel>>::new();
+    let mut inversions_per_ref = HashMap::<usize, Vec<Inversion>>::new();
+    let mut exons_per_ref = HashMap::<usize, Vec<(usize, usize, usize, usize)>>::new();
+    for e in matches_per_ref.iter_mut() {
         let (r_id, matches_raw) = e;
+        {
+            let rinfo = ref_map.get(r_id).unwrap();
+            if rinfo.circular {unwrap_circular(matches_raw, rinfo.len);}
+        }
         let mut c = Chain::new(matches_raw);
         let tp = c.get_match(params);
+        stats.add_anchors(*r_id, matches_raw, &c);
         if let Some(t) = tp {all_pseudocoords.push((*r_id, t));}
+        if params.inversions && tp.is_some() {inversions_per_ref.insert(*r_id, c.find_inversions(matches_raw, params));}
+        if params.splice && tp.is_some() {exons_per_ref.insert(*r_id, c.exons(params.min_intron));}
+        // in spliced mode, deletions of at least min_intron bases are introns rather than indels
+        let indels = if params.splice {c.indels.into_iter().filter(|i| i.kind != 'D' || i.len < params.min_intron).collect::<Vec<Indel>>()} else {c.indels};
+        if !indels.is_empty() {indels_per_ref.insert(*r_id, indels);}
     }
+    let hap = stats.haplotype();
     let coords_count = all_pseudocoords.len();
-    match coords_count {
+    stats.nb_chains = coords_count;
+    let best = match coords_count {
         0 => None,
-        1 => Some(find_coords(q_id, q_len, ref_map,  &all_pseudocoords[0])),
-        _ => determine_best_match(q_id, q_len, ref_map, &all_pseudocoords, coords_count),
+        1 => Some(all_pseudocoords[0]),
+        // haplotype mode: the read is placed on its haplotype, and homologous chains on the other one compete for MAPQ
+        _ if params.haplotypes => determine_best_haplotype_match(&all_pseudocoords, ref_map, hap),
+        // graph mode: chains on paths traversing the same segments are the same placement
+        _ => match graph {
+            Some(g) => g.best_path_match(&all_pseudocoords),
+            None => determine_best_match(&all_pseudocoords, coords_count).copied(),
+        },
+    };
+    let t = match best {
+        Some(t) => t,
+        None => {
+            stats.set_unmapped(coords_count);
+            return None;
+        }
+    };
+    let t = &t;
+    stats.score = t.1.5;
+    stats.mapq = t.1.6;
+    stats.r_idx = t.0;
+    stats.indels = indels_per_ref.remove(&t.0).unwrap_or_default();
+    stats.inversions = inversions_per_ref.remove(&t.0).unwrap_or_default();
+    stats.exons = exons_per_ref.remove(&t.0).unwrap_or_default();
+    if ref_map.get(&t.0).unwrap().decoy {
+        stats.status = Status::Decoy;
+        return None;
     }
+    stats.status = Status::Mapped;
+    let mut line = find_coords(q_id, q_len, ref_map, t, stats);
+    if params.haplotypes {
+        let tags = format!("\thp:i:{}\th1:i:{}\th2:i:{}", hap, stats.nb_hap1, stats.nb_hap2);
+        match line.find('\n') {
+            Some(i) => line.insert_str(i, &tags),
+            None => line.push_str(&tags),
+        }
+    }
+    Some(line)
        /* let (v, c) = &final_matches[0];
         if params.a {
             let (q_coords, r_coords) = c.get_remaining_seqs(&v);
@@ -101,10 +235,27 @@ pub fn find_matches(q_id: &str, q_len: usize, q_str: &[u8], ref_map: &DashMap<us
         }*/
 }
 
-pub fn determine_best_match(q_id: &str, q_len: usize, ref_map: &DashMap<usize, (String, usize)>, all_pseudocoords: &[PseudoChainCoordsTuple], coords_count: usize) -> Option<String> {
+pub fn determine_best_match<'a>(all_pseudocoords: &'a [PseudoChainCoordsTuple<'a>], coords_count: usize) -> Option<&'a PseudoChainCoordsTuple<'a>> {
     let (max_i, _, max_count, next_max_count) = find_largest_two_chains(all_pseudocoords, coords_count);
     if max_count == next_max_count {return None;}
-    else {return Some(find_coords(q_id, q_len, ref_map, &all_pseudocoords[max_i]));}
+    else {return Some(&all_pseudocoords[max_i]);}
+}
+
+// Best chain of a read in haplotype mode, with its MAPQ. Chains on the haplotype the read is assigned to (hap, see Stats::haplotype) are preferred
+// whenever there is one, and otherwise ties are broken towards haplotype 1 (homozygous or unassigned reads); ties within the preferred chains are ambiguous.
+// MAPQ is 0 if any other chain, on either haplotype, has at least as many matches as the chosen one.
+pub fn determine_best_haplotype_match<'a>(all_pseudocoords: &[PseudoChainCoordsTuple<'a>], ref_map: &DashMap<usize, RefInfo>, hap: usize) -> Option<PseudoChainCoordsTuple<'a>> {
+    let bit = if hap > 0 {1u8 << (hap - 1)} else {0};
+    let on_hap = |r: &usize| bit != 0 && ref_map.get(r).unwrap().hap == bit;
+    let pool = all_pseudocoords.iter().enumerate().filter(|(_, (r, _))| on_hap(r)).collect::<Vec<_>>();
+    let pool = if pool.is_empty() {all_pseudocoords.iter().enumerate().collect::<Vec<_>>()} else {pool};
+    let key = |(r, c): &PseudoChainCoordsTuple| (c.5, std::cmp::Reverse(ref_map.get(r).unwrap().hap));
+    let (best_i, best) = *pool.iter().max_by_key(|(_, t)| key(t)).unwrap();
+    if pool.iter().any(|(i, t)| *i != best_i && key(t) == key(best)) {return None;}
+    let contested = all_pseudocoords.iter().enumerate().any(|(i, (_, c))| i != best_i && c.5 >= best.1.5);
+    let mut t = *best;
+    if contested {t.1.6 = 0;}
+    Some(t)
 }
 
 pub fn find_largest_two_chains(all_pseudocoords: &[PseudoChainCoordsTuple], coords_count: usize) -> (usize, usize, usize, usize) {
@@ -128,11 +279,13 @@ pub fn find_largest_two_chains(all_pseudocoords: &[PseudoChainCoordsTuple], coor
     (max, second_max, max_count, second_max_count)
 }
 
-pub fn find_coords(q_id: &str, q_len: usize, ref_map: &DashMap<usize, (String, usize)>, t: &PseudoChainCoordsTuple) -> String {
+// Extend the chain coordinates to the whole query and format the PAF line. The covered reference spans (without introns and large deletions) are recorded in stats.
+pub fn find_coords(q_id: &str, q_len: usize, ref_map: &DashMap<usize, RefInfo>, t: &PseudoChainCoordsTuple, stats: &mut Stats) -> String {
     let (r_idx, coords) = *t;
-    let rtup = ref_map.get(&r_idx).unwrap();
-    let r_id = &rtup.0;
-    let r_len = rtup.1;
+    let rinfo = ref_map.get(&r_idx).unwrap();
+    let r_id = &rinfo.name;
+    let r_len = rinfo.len;
+    if rinfo.circular {return find_coords_circular(q_id, q_len, r_id, r_len, coords, stats);}
     let (rc, q_start, q_end, r_start, r_end, score, mapq) = coords;
     let final_r_start;
     let final_r_end;
@@ -178,6 +331,111 @@ pub fn find_coords(q_id: &str, q_len: usize, ref_map: &DashMap<usize, (String, u
     let final_q_start = q_start - exc_s;
     let final_q_end = q_end + exc_e;
     let rc_s : &str = match rc {true => "-", false => "+"};
+    let spans = covered_spans(final_r_start, final_r_end + 1, &skipped_intervals(stats, rc, 0));
+    stats.r_spans.extend(spans);
     let paf_line = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", q_id, q_len, final_q_start, final_q_end, rc_s, r_id, r_len, final_r_start, final_r_end, score, r_len, mapq);
-    paf_line
+    let (tags, supplementary) = indel_inversion_records(q_id, q_len, rc, r_id, r_len, mapq, stats);
+    format!("{}{}{}", paf_line, tags, supplementary)
+}
+
+// Reference intervals skipped by the reported mapping, in chain coordinates plus shift: introns between exons (spliced mode)
+// and large deletions chained over (their length from the start of the reference gap containing them), sorted.
+fn skipped_intervals(stats: &Stats, rc: bool, shift: i64) -> Vec<(usize, usize)> {
+    let mut exons = stats.exons.iter().map(|e| (e.2, e.3)).collect::<Vec<(usize, usize)>>();
+    exons.sort_unstable();
+    let mut skipped = exons.windows(2).filter(|w| w[1].0 > w[0].1).map(|w| (w[0].1, w[1].0)).collect::<Vec<(usize, usize)>>();
+    for i in stats.indels.iter().filter(|i| i.kind == 'D') {
+        // on the reverse strand, the gap extends to lower reference positions
+        skipped.push(if rc {(i.r_pos.saturating_sub(i.len), i.r_pos)} else {(i.r_pos, i.r_pos + i.len)});
+    }
+    skipped.sort_unstable();
+    skipped.iter().map(|&(s, e)| ((s as i64 + shift).max(0) as usize, (e as i64 + shift).max(0) as usize)).collect()
+}
+
+// Parts of the reference span [start, end) of a mapping outside the skipped intervals (sorted), i.e. the spans it covers.
+fn covered_spans(start: usize, end: usize, skipped: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut s = start;
+    for &(g_s, g_e) in skipped {
+        if g_e <= s || g_s >= end {continue;}
+        if g_s > s {spans.push((s, g_s));}
+        s = g_e;
+    }
+    if s < end {spans.push((s, end));}
+    spans
+}
+
+// Tags of the large indels (li:Z) and inverted segments (iv:Z) of a mapping, and the supplementary records of its inverted segments
+// (each line preceded by a newline). Reference positions are taken modulo the reference length, as Matches of circular references are unwrapped.
+pub fn indel_inversion_records(q_id: &str, q_len: usize, rc: bool, r_id: &str, r_len: usize, mapq: usize, stats: &Stats) -> (String, String) {
+    let (mut tags, mut supplementary) = (String::new(), String::new());
+    // large indels chained over, as <length><D|I>@<query position>:<reference position>
+    if !stats.indels.is_empty() {
+        tags.push_str(&format!("\tli:Z:{}", stats.indels.iter().map(|i| format!("{}{}@{}:{}", i.len, i.kind, i.q_pos, i.r_pos % r_len)).collect::<Vec<String>>().join(",")));
+    }
+    // inverted segments: listed in the iv:Z tag of the mapping, and each reported as a supplementary record with its breakpoint intervals
+    if !stats.inversions.is_empty() {
+        tags.push_str(&format!("\tiv:Z:{}", stats.inversions.iter().map(|i| format!("{}-{}", i.r_start % r_len, (i.r_end - 1) % r_len)).collect::<Vec<String>>().join(",")));
+        let inv_rc_s = if rc {"+"} else {"-"};
+        for i in stats.inversions.iter() {
+            supplementary.push_str(&format!("\n{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\ttp:A:S\tib:Z:{}-{},{}-{}", q_id, q_len, i.q_start, i.q_end - 1, inv_rc_s, r_id, r_len, i.r_start % r_len, (i.r_end - 1) % r_len, i.count, r_len, mapq,
+                                       i.left_bp.0 % r_len, i.left_bp.1 % r_len, i.right_bp.0 % r_len, i.right_bp.1 % r_len));
+        }
+    }
+    (tags, supplementary)
+}
+
+// Final coordinates on a circular reference. Query ends are extended around the origin instead of being clamped,
+// and a mapping that crosses the origin is reported as two PAF records split at the origin: the first one primary (tp:A:P), the second one supplementary (tp:A:S).
+pub fn find_coords_circular(q_id: &str, q_len: usize, r_id: &str, r_len: usize, coords: PseudoChainCoords, stats: &mut Stats) -> String {
+    let (rc, q_start, q_end, r_start, r_end, score, mapq) = coords;
+    let rc_s : &str = match rc {true => "-", false => "+"};
+    let (ext_s, ext_e) = match rc {
+        false => (q_start, q_len - q_end - 1),
+        true => (q_len - q_end - 1, q_start),
+    };
+    let (mut final_q_start, mut final_q_end) = (0, q_len - 1);
+    let mut final_r_start = r_start as i64 - ext_s as i64;
+    let mut final_r_end = (r_end + ext_e) as i64;
+    if final_r_end - final_r_start + 1 > r_len as i64 {
+        // query longer than the reference: don't extend past the chain
+        final_q_start = q_start;
+        final_q_end = q_end;
+        final_r_start = r_start as i64;
+        final_r_end = r_end as i64;
+    }
+    let r_len_i = r_len as i64;
+    let chain_r_start = final_r_start;
+    while final_r_start < 0 {
+        final_r_start += r_len_i;
+        final_r_end += r_len_i;
+    }
+    while final_r_start >= r_len_i {
+        final_r_start -= r_len_i;
+        final_r_end -= r_len_i;
+    }
+    // covered spans, split at the origin (the chain coordinates of skipped intervals are shifted as the mapping)
+    let skipped = skipped_intervals(stats, rc, final_r_start - chain_r_start);
+    let (final_r_start, final_r_end) = (final_r_start as usize, final_r_end as usize);
+    for (s, e) in covered_spans(final_r_start, final_r_end + 1, &skipped) {
+        if e <= r_len {stats.r_spans.push((s, e));}
+        else if s >= r_len {stats.r_spans.push((s - r_len, e - r_len));}
+        else {stats.r_spans.extend([(s, r_len), (0, e - r_len)]);}
+    }
+    let (tags, supplementary) = indel_inversion_records(q_id, q_len, rc, r_id, r_len, mapq, stats);
+    if final_r_end < r_len {
+        return format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}{}", q_id, q_len, final_q_start, final_q_end, rc_s, r_id, r_len, final_r_start, final_r_end, score, r_len, mapq, tags, supplementary);
+    }
+    // split at the origin: [final_r_start, r_len - 1] and [0, final_r_end - r_len]
+    let len_before = r_len - final_r_start;
+    let len_after = final_r_end - r_len + 1;
+    let ((q_s_1, q_e_1), (q_s_2, q_e_2)) = match rc {
+        false => ((final_q_start, final_q_start + len_before - 1), (final_q_start + len_before, final_q_end)),
+        true => ((final_q_start + len_after, final_q_end), (final_q_start, final_q_start + len_after - 1)),
+    };
+    let score_1 = score * len_before / (len_before + len_after);
+    let score_2 = score - score_1;
+    let paf_line_1 = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\ttp:A:P{}", q_id, q_len, q_s_1, q_e_1, rc_s, r_id, r_len, final_r_start, r_len - 1, score_1, r_len, mapq, tags);
+    let paf_line_2 = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\ttp:A:S", q_id, q_len, q_s_2, q_e_2, rc_s, r_id, r_len, 0, final_r_end - r_len, score_2, r_len, mapq);
+    format!("{}\n{}{}", paf_line_1, paf_line_2, supplementary)
 }
@@ -0,0 +1,174 @@
+// overlap.rs
+// Contains the "overlap" subcommand, an all-vs-all read overlapper: reads are indexed with all occurrences of their k-min-mers (MultiIndex),
+// then the Matches of each read against every other read are chained, and overlaps are written as PAF.
+
+use crate::{Params, chain::Chain, closures, get_reader, index::{Entry, MultiIndex, ReadOnlyMultiIndex, RefInfo}, is_fasta_filename, mers, presets::SeedingOpt, r#match::Match};
+use dashmap::DashMap;
+use rust_seq2kminmers::Kminmer;
+use seq_io::BaseRecord;
+use seq_io::parallel::{read_process_fasta_records, read_process_fastq_records};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct OverlapOpt {
+    /// Reads (FASTA/FASTQ, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub reads: PathBuf,
+    /// Output prefix for PAF file
+    ///
+    #[structopt(short, long, default_value = "mapquik-overlaps")]
+    pub prefix: String,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+    /// Minimum number of matching k-min-mers in an overlap
+    ///
+    #[structopt(long, default_value = "5")]
+    pub min_seeds: usize,
+    /// Minimum overlap length
+    ///
+    #[structopt(long, default_value = "2000")]
+    pub min_length: usize,
+    /// Maximum overhang
+    ///
+    /// Overlaps whose chain stops further than this from
+    /// the ends of both reads, on either side, are internal
+    /// matches (e.g. repeats) rather than true overlaps.
+    #[structopt(long, default_value = "1000")]
+    pub max_overhang: usize,
+    /// Report internal matches too
+    ///
+    #[structopt(long)]
+    pub keep_internal: bool,
+    /// Maximum number of occurrences of a k-min-mer
+    ///
+    /// More frequent k-min-mers (repeats) are not used.
+    #[structopt(long, default_value = "200")]
+    pub max_occ: usize,
+}
+
+// Type of an overlap, given chain coordinates (0-based, half-open), with target coordinates on the target's forward strand.
+pub fn classify(q_len: usize, q_start: usize, q_end: usize, t_len: usize, t_start: usize, t_end: usize, rc: bool, max_overhang: usize) -> &'static str {
+    // target coordinates on the query strand
+    let (t_start, t_end) = if rc {(t_len - t_end, t_len - t_start)} else {(t_start, t_end)};
+    let overhang_left = q_start.min(t_start);
+    let overhang_right = (q_len - q_end).min(t_len - t_end);
+    if overhang_left > max_overhang || overhang_right > max_overhang {"internal"}
+    else if q_start <= t_start && q_len - q_end <= t_len - t_end {"query_contained"}
+    else if t_start <= q_start && t_len - t_end <= q_len - q_end {"target_contained"}
+    else {"dovetail"}
+}
+
+// Matches of a read against all reads with a larger ID (so that each pair is considered once, and self-hits are excluded).
+// Consecutive query k-min-mers that are consecutive in a target are merged into a single Match, as in Match::extend.
+pub fn overlap_matches(q_idx: usize, q_str: &[u8], params: &Params, index: &ReadOnlyMultiIndex) -> HashMap<usize, Vec<Match>> {
+    let mut matches_per_target = HashMap::<usize, Vec<Match>>::new();
+    let mut last = HashMap::<usize, (usize, Entry)>::new(); // per target: offset of the last query k-min-mer, and its Entry
+    if let Some(iter) = mers::extract("", q_str, params) {
+        for q in iter {
+            for e in index.get(&q.get_hash()).iter().filter(|e| e.id > q_idx) {
+                let matches = matches_per_target.entry(e.id).or_insert_with(Vec::new);
+                let extends = match (last.get(&e.id), matches.last()) {
+                    (Some((q_offset, p)), Some(h)) => q.offset == q_offset + 1 && h.check(&q, e, p),
+                    _ => false,
+                };
+                if extends {matches.last_mut().unwrap().update(&q, e);}
+                else {matches.push(Match::new(&q, e));}
+                last.insert(e.id, (q.offset, e.clone()));
+            }
+        }
+    }
+    matches_per_target
+}
+
+// Run the overlap subcommand.
+pub fn run(opt: &OverlapOpt) {
+    let params = opt.seeding.to_params("hifi");
+    let threads = opt.seeding.preset.threads;
+    let fasta = is_fasta_filename(&opt.reads);
+
+    // Number reads in file order (a pair's overlap is reported from the read with the lower index, so indices must not depend on thread scheduling)
+    let start = Instant::now();
+    let read_map : DashMap<usize, RefInfo> = DashMap::new(); // Read names and lengths
+    let read_ids : DashMap<String, usize> = DashMap::new();
+    closures::for_each_record(&opt.reads, fasta, |id, _, seq| {
+        let idx = read_map.len();
+        read_map.insert(idx, RefInfo::new(id, seq.len(), false, false, 0));
+        if read_ids.insert(id.to_string(), idx).is_some() {panic!("Duplicate read name: {}.", id);}
+    });
+
+    // Index all k-min-mer occurrences of all reads
+    let mers_index = MultiIndex::new();
+    let index_read = |seq: &[u8], id: &str| -> Option<u64> {
+        let idx = *read_ids.get(id).unwrap();
+        if let Some(iter) = mers::extract(id, seq, &params) {
+            for kminmer in iter {mers_index.add_with_mer(idx, &kminmer);}
+        }
+        Some(1)
+    };
+    let (buf, _) = get_reader(&opt.reads);
+    if fasta {
+        let reader = seq_io::fasta::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fasta_records(reader, threads as u32, threads, |record, found| {*found = index_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, _found: &mut Option<u64>| {None::<()>}).ok();
+    }
+    else {
+        let reader = seq_io::fastq::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fastq_records(reader, threads as u32, threads, |record, found| {*found = index_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, _found: &mut Option<u64>| {None::<()>}).ok();
+    }
+    let nb_kminmers = mers_index.index.len();
+    let (index, nb_dropped) = mers_index.into_read_only(opt.max_occ);
+    println!("Indexed {} distinct k-min-mers from {} reads in {:?} ({} occurring more than {} times were dropped).", nb_kminmers, read_map.len(), start.elapsed(), nb_dropped, opt.max_occ);
+
+    // Chain the Matches of each read against every other read
+    let paf_filename = format!("{}.paf", opt.prefix);
+    let mut paf_file = match File::create(&paf_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", paf_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let overlap_read = |seq: &[u8], id: &str| -> Vec<String> {
+        let q_idx = *read_ids.get(id).unwrap();
+        let q_len = seq.len();
+        let mut lines = Vec::new();
+        let mut matches_per_target = overlap_matches(q_idx, seq, &params, &index).into_iter().collect::<Vec<_>>();
+        matches_per_target.sort_unstable_by_key(|(t_idx, _)| *t_idx);
+        for (t_idx, matches) in matches_per_target.iter() {
+            if matches.iter().map(|h| h.count).sum::<usize>() < opt.min_seeds {continue;}
+            let mut c = Chain::new(matches);
+            let (rc, q_start, q_end, t_start, t_end, score, _) = match c.get_match(&params) {
+                Some(t) => t,
+                None => continue,
+            };
+            let (q_end, t_end) = (q_end + 1, t_end + 1);
+            if score < opt.min_seeds || q_end - q_start < opt.min_length {continue;}
+            let t = read_map.get(t_idx).unwrap();
+            let ov_type = classify(q_len, q_start, q_end, t.len, t_start, t_end, rc, opt.max_overhang);
+            if ov_type == "internal" && !opt.keep_internal {continue;}
+            let block_len = (q_end - q_start).max(t_end - t_start);
+            lines.push(format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tcm:i:{}\tov:Z:{}", id, q_len, q_start, q_end, if rc {"-"} else {"+"}, t.name, t.len, t_start, t_end, score, block_len, 255, c.len(), ov_type));
+        }
+        lines
+    };
+    let mut nb_overlaps = 0;
+    let mut write_lines = |lines: &mut Vec<String>| {
+        for l in lines.iter() {writeln!(paf_file, "{}", l).expect("Error writing line.");}
+        nb_overlaps += lines.len();
+        None::<()>
+    };
+    let start = Instant::now();
+    let (buf, _) = get_reader(&opt.reads);
+    if fasta {
+        let reader = seq_io::fasta::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fasta_records(reader, threads as u32, params.q, |record, found| {*found = overlap_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, found| {write_lines(found)}).ok();
+    }
+    else {
+        let reader = seq_io::fastq::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fastq_records(reader, threads as u32, params.q, |record, found| {*found = overlap_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, found| {write_lines(found)}).ok();
+    }
+    println!("Found {} overlaps in {:?}.", nb_overlaps, start.elapsed());
+    println!("Wrote overlaps to {}.", paf_filename);
+}
@@ -0,0 +1,147 @@
+// paf.rs
+// Contains the "PafRecord" struct, a mapping record parsed from a PAF or SAM line, used by the evaluation and comparison subcommands.
+
+use crate::get_reader;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PafRecord {
+    pub q_name: String, // Query name
+    pub q_len: usize, // Query length
+    pub q_start: usize, // Query start location
+    pub q_end: usize, // Query end location
+    pub rc: bool, // Strand direction
+    pub t_name: String, // Reference name
+    pub t_len: usize, // Reference length
+    pub t_start: usize, // Reference start location
+    pub t_end: usize, // Reference end location
+    pub matches: usize, // Number of matching bases (or seeds)
+    pub block_len: usize, // Alignment block length
+    pub mapq: usize, // Mapping quality
+    pub primary: bool, // False for secondary or supplementary records
+    pub identity: Option<f64>, // Alignment identity, if given by the mapper (de:f or NM:i tags)
+}
+impl PafRecord {
+
+    // Parse a PAF line (None if malformed).
+    pub fn from_paf_line(line: &str) -> Option<Self> {
+        let f : Vec<&str> = line.split('\t').collect();
+        if f.len() < 12 {return None;}
+        let mut rec = PafRecord {
+            q_name: f[0].to_string(),
+            q_len: f[1].parse().ok()?,
+            q_start: f[2].parse().ok()?,
+            q_end: f[3].parse().ok()?,
+            rc: f[4] == "-",
+            t_name: f[5].to_string(),
+            t_len: f[6].parse().ok()?,
+            t_start: f[7].parse().ok()?,
+            t_end: f[8].parse().ok()?,
+            matches: f[9].parse().ok()?,
+            block_len: f[10].parse().ok()?,
+            mapq: f[11].parse().ok()?,
+            primary: true,
+            identity: None,
+        };
+        for tag in &f[12..] {
+            if tag.starts_with("tp:A:") {rec.primary = !(tag.ends_with('S') || tag.ends_with('s'));}
+            else if let Some(de) = tag.strip_prefix("de:f:") {rec.identity = de.parse::<f64>().ok().map(|d| 1.0 - d);}
+            else if let Some(nm) = tag.strip_prefix("NM:i:") {
+                if rec.identity.is_none() && rec.block_len > 0 {rec.identity = nm.parse::<usize>().ok().map(|n| 1.0 - n as f64 / rec.block_len as f64);}
+            }
+        }
+        Some(rec)
+    }
+
+    // Parse a SAM line (None for header lines, unmapped reads and malformed lines). Reference lengths are not known from the line alone and set to 0.
+    pub fn from_sam_line(line: &str) -> Option<Self> {
+        if line.starts_with('@') {return None;}
+        let f : Vec<&str> = line.split('\t').collect();
+        if f.len() < 11 {return None;}
+        let flag : usize = f[1].parse().ok()?;
+        if flag & 0x4 != 0 || f[5] == "*" {return None;}
+        let (mut clip_s, mut clip_e, mut q_aln, mut t_aln, mut deletions) = (0, 0, 0, 0, 0);
+        let mut num = 0;
+        let mut seen_aln = false;
+        for c in f[5].chars() {
+            if let Some(d) = c.to_digit(10) {num = num * 10 + d as usize; continue;}
+            match c {
+                'S' | 'H' => {if seen_aln {clip_e += num;} else {clip_s += num;}}
+                'M' | '=' | 'X' => {q_aln += num; t_aln += num; seen_aln = true;}
+                'I' => {q_aln += num; seen_aln = true;}
+                'D' => {t_aln += num; deletions += num; seen_aln = true;}
+                'N' => {t_aln += num; seen_aln = true;}
+                _ => {}
+            }
+            num = 0;
+        }
+        let rc = flag & 0x10 != 0;
+        let q_len = clip_s + q_aln + clip_e;
+        let (q_start, q_end) = if rc {(clip_e, clip_e + q_aln)} else {(clip_s, clip_s + q_aln)};
+        let t_start = f[3].parse::<usize>().ok()?.saturating_sub(1);
+        let block_len = q_aln.max(t_aln);
+        let mut identity = None;
+        for tag in &f[11..] {
+            if let Some(nm) = tag.strip_prefix("NM:i:") {
+                // alignment columns: M, I (both in q_aln) and D
+                let blen = q_aln + deletions;
+                if blen > 0 {identity = nm.parse::<usize>().ok().map(|n| 1.0 - n as f64 / blen as f64);}
+            }
+        }
+        Some(PafRecord {
+            q_name: f[0].to_string(),
+            q_len,
+            q_start,
+            q_end,
+            rc,
+            t_name: f[2].to_string(),
+            t_len: 0,
+            t_start,
+            t_end: t_start + t_aln,
+            matches: q_aln,
+            block_len,
+            mapq: f[4].parse().ok()?,
+            primary: flag & 0x900 == 0,
+            identity,
+        })
+    }
+}
+
+// Read all mapping records of a PAF or SAM file (raw or compressed). SAM is detected from the file extension or a leading header line.
+pub fn read_records(path: &PathBuf) -> Vec<PafRecord> {
+    let path_str = path.to_str().unwrap();
+    let mut is_sam = path_str.ends_with(".sam") || path_str.contains(".sam.");
+    let (buf, _) = get_reader(path);
+    let mut records = Vec::new();
+    for (i, line) in buf.lines().enumerate() {
+        let line = line.expect("Error reading mapping file.");
+        if i == 0 && line.starts_with('@') {is_sam = true;}
+        if line.is_empty() {continue;}
+        let rec = if is_sam {PafRecord::from_sam_line(&line)} else {PafRecord::from_paf_line(&line)};
+        if let Some(r) = rec {records.push(r);}
+    }
+    records
+}
+
+// Overlap of two intervals divided by the length of their union.
+pub fn overlap_ratio(s1: usize, e1: usize, s2: usize, e2: usize) -> f64 {
+    let (lo, hi) = (s1.max(s2), e1.min(e2));
+    if hi <= lo {return 0.0;}
+    (hi - lo) as f64 / (e1.max(e2) - s1.min(s2)) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sam_identity_counts_each_alignment_column_once() {
+        let line = "read1\t0\tchr1\t101\t60\t10M2I5M3D10M\t*\t0\t0\t*\t*\tNM:i:5";
+        let rec = PafRecord::from_sam_line(line).unwrap();
+        assert_eq!((rec.q_start, rec.q_end, rec.q_len), (0, 27, 27));
+        assert_eq!((rec.t_start, rec.t_end), (100, 128));
+        let identity = rec.identity.unwrap();
+        assert!((identity - (1.0 - 5.0 / 30.0)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,106 @@
+// presets.rs
+// Contains the "Preset" struct, a named set of seeding and chaining parameters for a read technology or assembly divergence,
+// and the command-line options selecting a preset and overriding it, shared by the subcommands.
+
+use crate::{FH, Params};
+use structopt::StructOpt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub k: usize, // k-min-mer length
+    pub l: usize, // l-mer (minimizer) length
+    pub density: FH, // minimizer density
+    pub c: usize, // minimum chain length
+    pub s: usize, // minimum match score (# of matching seeds)
+    pub g: usize, // maximum gap difference
+    pub use_hpc: bool, // homopolymer-compressed hashing
+}
+
+pub const PRESETS: [Preset; 5] = [
+    // PacBio HiFi (default parameters)
+    Preset {name: "hifi", k: 5, l: 31, density: 0.01, c: 4, s: 11, g: 2000, use_hpc: true},
+    // ONT duplex, close to HiFi accuracy: longer k-min-mers for more specific seeds
+    Preset {name: "duplex", k: 6, l: 31, density: 0.01, c: 4, s: 11, g: 2000, use_hpc: true},
+    // ONT R10 simplex reads filtered at Q20: shorter seeds, fewer required, more gap tolerance
+    Preset {name: "ont-r10-q20", k: 4, l: 25, density: 0.01, c: 3, s: 8, g: 3000, use_hpc: true},
+    // Assembly to reference, up to ~5% divergence: no homopolymer errors, longer gaps
+    Preset {name: "asm5", k: 3, l: 25, density: 0.01, c: 3, s: 5, g: 10000, use_hpc: false},
+    // Assembly to reference, up to ~20% divergence: short and dense seeds
+    Preset {name: "asm20", k: 2, l: 19, density: 0.02, c: 2, s: 3, g: 20000, use_hpc: false},
+];
+
+// Get a preset by name.
+pub fn get(name: &str) -> &'static Preset {
+    match PRESETS.iter().find(|p| p.name == name) {
+        Some(p) => p,
+        None => panic!("Unknown preset: {} (available: {}).", name, PRESETS.iter().map(|p| p.name).collect::<Vec<&str>>().join(", ")),
+    }
+}
+
+// Preset options of a subcommand: the preset, and the flags overriding its gap difference and hashing mode.
+#[derive(Debug, StructOpt)]
+pub struct PresetOpt {
+    /// Parameter preset (hifi, duplex, ont-r10-q20, asm5, asm20)
+    ///
+    /// Individual flags override the preset. Defaults to asm5
+    /// for asm and scaffold, and to hifi otherwise.
+    #[structopt(long = "preset")]
+    pub name: Option<String>,
+    /// Maximum nucleotide gap length difference
+    ///
+    #[structopt(short, long)]
+    pub gap_diff: Option<usize>,
+    /// Number of threads
+    ///
+    #[structopt(long, default_value = "8")]
+    pub threads: usize,
+    /// Deactivate HomoPolymer Compression
+    #[structopt(long)]
+    pub nohpc: bool,
+    /// Activate HomoPolymer Compression (overrides the preset)
+    #[structopt(long, conflicts_with = "nohpc")]
+    pub hpc: bool,
+}
+impl PresetOpt {
+
+    // The selected preset, or the subcommand's default one.
+    pub fn get(&self, default_preset: &str) -> &'static Preset {
+        get(self.name.as_deref().unwrap_or(default_preset))
+    }
+
+    // Params for the given seeding parameters, with the gap difference and hashing mode of the preset unless overridden.
+    pub fn to_params(&self, default_preset: &str, k: usize, l: usize, density: FH) -> Params {
+        let preset = self.get(default_preset);
+        let mut params = Params::seeding(k, l, density, (preset.use_hpc || self.hpc) && !self.nohpc);
+        params.g = self.gap_diff.unwrap_or(preset.g);
+        params
+    }
+}
+
+// Seeding options of a subcommand: k, l and density (from the preset unless given), and the preset options.
+#[derive(Debug, StructOpt)]
+pub struct SeedingOpt {
+    #[structopt(flatten)]
+    pub preset: PresetOpt,
+    /// k-min-mer length
+    ///
+    #[structopt(short, long)]
+    pub k: Option<usize>,
+    /// l-mer (minimizer) length
+    ///
+    #[structopt(short, long)]
+    pub l: Option<usize>,
+    /// Density threshold for density-based selection scheme
+    ///
+    #[structopt(short, long)]
+    pub density: Option<FH>,
+}
+impl SeedingOpt {
+
+    // Params from the options, the selected preset or default_preset.
+    pub fn to_params(&self, default_preset: &str) -> Params {
+        let preset = self.preset.get(default_preset);
+        self.preset.to_params(default_preset, self.k.unwrap_or(preset.k), self.l.unwrap_or(preset.l), self.density.unwrap_or(preset.density))
+    }
+}
@@ -0,0 +1,145 @@
+// report.rs
+// Writes a self-contained HTML report of a run (inline SVG charts and CSS, no external dependencies) from its Summary.
+
+use crate::{Params, get_memory_rusage, index::RefInfo, summary::{Summary, LEN_BIN, MAX_MAPQ, NB_LEN_BINS, NB_SEED_BINS, SEED_BIN}, stats::Status};
+use dashmap::DashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const CHART_W: f64 = 760.0;
+const CHART_H: f64 = 260.0;
+const MARGIN_L: f64 = 70.0;
+const MARGIN_B: f64 = 60.0;
+const MAX_REFS: usize = 50; // references with the most mapped reads are shown
+
+// Escape a string for HTML/SVG text.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// An SVG bar chart of values with one label per bar (labels are thinned out when there are many bars).
+// Values are formatted with the given number of decimals in bar tooltips.
+pub fn bar_chart(labels: &[String], values: &[f64], x_label: &str, y_label: &str, color: &str, decimals: usize) -> String {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    let max = if max > 0.0 {max} else {1.0};
+    let n = values.len().max(1);
+    let bar_w = CHART_W / n as f64;
+    let label_step = (n + 19) / 20; // at most ~20 labels
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n", CHART_W + MARGIN_L + 10.0, CHART_H + MARGIN_B + 10.0);
+    svg.push_str(&format!("<line x1=\"{0}\" y1=\"10\" x2=\"{0}\" y2=\"{1}\" class=\"axis\"/><line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" class=\"axis\"/>\n", MARGIN_L, CHART_H + 10.0, MARGIN_L + CHART_W));
+    for t in 0..=4 {
+        let v = max * t as f64 / 4.0;
+        let y = 10.0 + CHART_H - CHART_H * t as f64 / 4.0;
+        svg.push_str(&format!("<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n", MARGIN_L - 5.0, y + 4.0, format_value(v, decimals)));
+    }
+    for (i, v) in values.iter().enumerate() {
+        let h = v / max * CHART_H;
+        let x = MARGIN_L + i as f64 * bar_w;
+        svg.push_str(&format!("<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>{}: {}</title></rect>\n",
+                              x + 1.0, 10.0 + CHART_H - h, (bar_w - 2.0).max(1.0), h, color, escape(&labels[i]), format_value(*v, decimals)));
+        if i % label_step == 0 {
+            svg.push_str(&format!("<text transform=\"translate({:.1},{:.1}) rotate(45)\">{}</text>\n", x + bar_w / 2.0, CHART_H + 24.0, escape(&labels[i])));
+        }
+    }
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n", MARGIN_L + CHART_W / 2.0, CHART_H + MARGIN_B + 5.0, escape(x_label)));
+    svg.push_str(&format!("<text transform=\"translate(14,{}) rotate(-90)\" text-anchor=\"middle\">{}</text>\n", 10.0 + CHART_H / 2.0, escape(y_label)));
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn format_value(v: f64, decimals: usize) -> String {
+    format!("{:.*}", decimals, v)
+}
+
+fn load(v: &[AtomicUsize]) -> Vec<usize> {
+    v.iter().map(|x| x.load(Ordering::Relaxed)).collect()
+}
+
+// Number of leading bins to show (up to the last non-empty one).
+fn used_bins(counts: &[usize]) -> usize {
+    counts.iter().rposition(|c| *c > 0).map_or(1, |i| i + 1)
+}
+
+// Labels of histogram bins of the given size (the last bin is open-ended).
+fn bin_labels(nb: usize, bin_size: usize, nb_bins: usize, unit: &str) -> Vec<String> {
+    (0..nb).map(|i| if i == nb_bins - 1 {format!("{}{}+", i * bin_size, unit)} else {format!("{}-{}{}", i * bin_size, (i + 1) * bin_size, unit)}).collect()
+}
+
+// Write the HTML report of a run to <prefix>.report.html.
+pub fn write_html(summary: &Summary, output_prefix: &str, params: &Params, ref_map: &DashMap<usize, RefInfo>) {
+    let report_path = format!("{}{}", output_prefix, ".report.html");
+    let mut f = match File::create(&report_path) {
+        Err(why) => panic!("Couldn't create {}: {}", report_path, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let nb_reads = summary.nb_reads.load(Ordering::Relaxed);
+    let nb_mapped = summary.nb_mapped.load(Ordering::Relaxed);
+    let pct = |a: usize, b: usize| if b == 0 {0.0} else {a as f64 * 100.0 / b as f64};
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>mapquik report: {}</title>\n", escape(output_prefix)));
+    html.push_str("<style>\nbody {font-family: sans-serif; margin: 2em; color: #222;}\ntable {border-collapse: collapse;}\ntd, th {border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right;}\n\
+                   th {background: #f0f0f0;}\nsvg {font-size: 11px;}\n.axis {stroke: #444;}\n</style>\n</head>\n<body>\n");
+    html.push_str(&format!("<h1>mapquik {} report: {}</h1>\n", env!("CARGO_PKG_VERSION"), escape(output_prefix)));
+
+    // Overview
+    html.push_str("<h2>Overview</h2>\n<table>\n");
+    let rows = [
+        ("Reads", nb_reads.to_string()),
+        ("Bases", summary.nb_bases.load(Ordering::Relaxed).to_string()),
+        ("Mapped reads", format!("{} ({:.2}%)", nb_mapped, pct(nb_mapped, nb_reads))),
+        ("Mapped at MAPQ 60", format!("{} ({:.2}%)", summary.mapq[MAX_MAPQ].load(Ordering::Relaxed), pct(summary.mapq[MAX_MAPQ].load(Ordering::Relaxed), nb_reads))),
+        ("References", ref_map.len().to_string()),
+        ("Unique / repeated k-min-mers", format!("{} / {}", summary.nb_unique, summary.nb_repeated)),
+        ("Parameters", format!("k={}, l={}, density={}, hpc={}, chain={}, seeds={}, gap_diff={}", params.k, params.l, params.density, params.use_hpc, params.c, params.s, params.g)),
+        ("Maximum RSS", format!("{:.3} GB", get_memory_rusage() as f64 / 1024.0 / 1024.0 / 1024.0)),
+    ];
+    for (name, value) in rows.iter() {html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, escape(value)));}
+    html.push_str("</table>\n");
+    html.push_str("<h3>Unmapped reads by reason</h3>\n<table>\n");
+    for s in Status::UNMAPPED.iter() {
+        let n = summary.get_unmapped(*s);
+        html.push_str(&format!("<tr><th>{}</th><td>{}</td><td>{:.2}%</td></tr>\n", s.name(), n, pct(n, nb_reads)));
+    }
+    html.push_str("</table>\n");
+
+    // Timing
+    let index_s = summary.index_duration.as_secs_f64();
+    let map_s = summary.map_duration.as_secs_f64();
+    html.push_str("<h2>Timing</h2>\n");
+    html.push_str(&bar_chart(&["indexing".to_string(), "mapping".to_string(), "total".to_string()], &[index_s, map_s, index_s + map_s], "phase", "seconds", "#8c564b", 2));
+
+    // Read lengths
+    let len_hist = load(&summary.len_hist);
+    let len_mapped = load(&summary.len_hist_mapped);
+    let nb_len = used_bins(&len_hist);
+    let len_labels = bin_labels(nb_len, LEN_BIN / 1000, NB_LEN_BINS, "kb");
+    html.push_str("<h2>Read length distribution</h2>\n");
+    html.push_str(&bar_chart(&len_labels, &len_hist[..nb_len].iter().map(|c| *c as f64).collect::<Vec<f64>>(), "read length", "reads", "#1f77b4", 0));
+    html.push_str("<h2>Mapped fraction by read length</h2>\n");
+    let fractions = (0..nb_len).map(|i| if len_hist[i] == 0 {0.0} else {len_mapped[i] as f64 / len_hist[i] as f64}).collect::<Vec<f64>>();
+    html.push_str(&bar_chart(&len_labels, &fractions, "read length", "mapped fraction", "#2ca02c", 3));
+
+    // MAPQ
+    let mapq = load(&summary.mapq);
+    html.push_str("<h2>MAPQ distribution (mapped reads)</h2>\n");
+    html.push_str(&bar_chart(&(0..=MAX_MAPQ).map(|q| q.to_string()).collect::<Vec<String>>(), &mapq.iter().map(|c| *c as f64).collect::<Vec<f64>>(), "MAPQ", "reads", "#9467bd", 0));
+
+    // Seeds
+    let seeds = load(&summary.seeds_hist);
+    let nb_seeds = used_bins(&seeds);
+    html.push_str("<h2>Seed hits per read</h2>\n<p>Number of k-min-mers of a read hitting a unique k-min-mer of the index.</p>\n");
+    html.push_str(&bar_chart(&bin_labels(nb_seeds, SEED_BIN, NB_SEED_BINS, ""), &seeds[..nb_seeds].iter().map(|c| *c as f64).collect::<Vec<f64>>(), "seed hits", "reads", "#ff7f0e", 0));
+
+    // References
+    let mut refs = ref_map.iter().map(|r| (*r.key(), r.name.clone(), summary.reads_per_ref.get(r.key()).map_or(0, |n| *n))).collect::<Vec<(usize, String, usize)>>();
+    refs.sort_unstable_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+    let nb_shown = refs.len().min(MAX_REFS);
+    html.push_str("<h2>Mapped reads per reference</h2>\n");
+    if refs.len() > MAX_REFS {html.push_str(&format!("<p>Top {} of {} references.</p>\n", MAX_REFS, refs.len()));}
+    html.push_str(&bar_chart(&refs[..nb_shown].iter().map(|r| r.1.clone()).collect::<Vec<String>>(), &refs[..nb_shown].iter().map(|r| r.2 as f64).collect::<Vec<f64>>(), "reference", "mapped reads", "#17becf", 0));
+    html.push_str("</body>\n</html>\n");
+    f.write_all(html.as_bytes()).expect("Error writing report.");
+    println!("Wrote HTML report to {}.", report_path);
+}
@@ -0,0 +1,225 @@
+// scaffold.rs
+// Contains the "scaffold" subcommand: reference-guided scaffolding of draft contigs. Contigs are placed on reference chromosomes using their asm Blocks,
+// then ordered and oriented along each chromosome, with gap sizes estimated from reference coordinates. Writes AGP, a scaffolded FASTA,
+// and the contigs whose blocks disagree on their placement (potential misassemblies).
+
+use crate::{asm::{self, Block}, closures, get_reader, is_fasta_filename, mask::Mask, presets::SeedingOpt, summary::Summary};
+use bio::alphabets::dna;
+use seq_io::BaseRecord;
+use seq_io::parallel::{read_process_fasta_records, read_process_fastq_records};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+const UNKNOWN_GAP: usize = 100; // AGP convention for gaps of unknown size
+const FASTA_WIDTH: usize = 80;
+
+#[derive(Debug, StructOpt)]
+pub struct ScaffoldOpt {
+    /// Draft contigs (FASTA/FASTQ, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub contigs: PathBuf,
+    /// Reference genome input
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub reference: PathBuf,
+    /// Output prefix for AGP, FASTA and conflicts files
+    ///
+    #[structopt(short, long, default_value = "mapquik-scaffold")]
+    pub prefix: String,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+    /// Minimum number of matching k-min-mers in a block
+    ///
+    #[structopt(long, default_value = "10")]
+    pub min_seeds: usize,
+    /// Minimum block length on the contig
+    ///
+    #[structopt(long, default_value = "10000")]
+    pub min_length: usize,
+    /// Number of preceding matches considered when chaining a match
+    ///
+    #[structopt(long, default_value = "50")]
+    pub lookback: usize,
+    /// Minimum fraction of a contig covered by blocks on its chromosome for it to be placed
+    ///
+    #[structopt(long, default_value = "0.1")]
+    pub min_aligned: f64,
+    /// Minimum fraction of a contig's aligned length elsewhere to flag a conflict
+    ///
+    /// Elsewhere means on another chromosome, on the other
+    /// strand, or at another position of the same chromosome.
+    #[structopt(long, default_value = "0.2")]
+    pub conflict_fraction: f64,
+    /// Maximum distance between the placements of two blocks of a contig
+    ///
+    /// Blocks placing the contig further apart on the same
+    /// chromosome are conflicting.
+    #[structopt(long, default_value = "100000")]
+    pub max_shift: usize,
+    /// Leave conflicting contigs unplaced
+    ///
+    #[structopt(long)]
+    pub skip_conflicts: bool,
+}
+
+// Placement of a contig on a reference chromosome.
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub r_idx: usize, // Reference ID
+    pub rc: bool, // Orientation of the contig
+    pub start: isize, // Reference position of the contig's first base (on the chromosome strand), may be negative
+    pub end: isize, // Reference position past the contig's last base
+    pub aligned: usize, // Contig bases covered by blocks of this placement
+}
+
+// Projected reference position of the first base of a contig (in chromosome orientation), according to a block.
+fn project(b: &Block, q_len: usize) -> isize {
+    if b.rc {b.r_start as isize - (q_len - b.q_end) as isize}
+    else {b.r_start as isize - b.q_start as isize}
+}
+
+// Place a contig from its primary blocks: on the chromosome and strand with the most aligned bases, at the position given by the largest block.
+// Also returns the reasons why other blocks conflict with this placement (empty if none).
+pub fn place(q_len: usize, blocks: &[Block], min_aligned: f64, conflict_fraction: f64, max_shift: usize) -> (Option<Placement>, Vec<String>) {
+    let primary = blocks.iter().filter(|b| b.primary).collect::<Vec<_>>();
+    let mut aligned = HashMap::<(usize, bool), usize>::new();
+    for b in primary.iter() {*aligned.entry((b.r_idx, b.rc)).or_insert(0) += b.q_end - b.q_start;}
+    let total = aligned.values().sum::<usize>();
+    let best = match aligned.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) {
+        Some((k, v)) => (*k, *v),
+        None => return (None, Vec::new()),
+    };
+    let ((r_idx, rc), best_aligned) = best;
+    let largest = primary.iter().filter(|b| b.r_idx == r_idx && b.rc == rc).max_by_key(|b| b.q_end - b.q_start).unwrap();
+    let start = project(largest, q_len);
+    let mut conflicts = Vec::new();
+    let min_conflict = (conflict_fraction * total as f64) as usize;
+    let other_refs = aligned.iter().filter(|(k, _)| k.0 != r_idx).map(|(_, v)| v).sum::<usize>();
+    if other_refs > min_conflict {conflicts.push(format!("other_chromosomes:{}", other_refs));}
+    let other_strand = aligned.get(&(r_idx, !rc)).cloned().unwrap_or(0);
+    if other_strand > min_conflict {conflicts.push(format!("other_strand:{}", other_strand));}
+    let shifted = primary.iter().filter(|b| b.r_idx == r_idx && b.rc == rc && (project(b, q_len) - start).unsigned_abs() > max_shift).map(|b| b.q_end - b.q_start).sum::<usize>();
+    if shifted > min_conflict {conflicts.push(format!("other_positions:{}", shifted));}
+    if (best_aligned as f64) < min_aligned * q_len as f64 {return (None, conflicts);}
+    (Some(Placement {r_idx, rc, start, end: start + q_len as isize, aligned: best_aligned}), conflicts)
+}
+
+// Write a sequence in FASTA format.
+fn write_fasta(f: &mut BufWriter<File>, name: &str, seq: &[u8]) {
+    writeln!(f, ">{}", name).expect("Error writing FASTA.");
+    for line in seq.chunks(FASTA_WIDTH) {
+        f.write_all(line).expect("Error writing FASTA.");
+        writeln!(f).expect("Error writing FASTA.");
+    }
+}
+
+// Run the scaffold subcommand.
+pub fn run(opt: &ScaffoldOpt) {
+    let params = opt.seeding.to_params("asm5");
+    let threads = opt.seeding.preset.threads;
+    let mut summary = Summary::new();
+    let (index, ref_map) = closures::build_index(&opt.reference, &params, threads, threads, is_fasta_filename(&opt.reference), &Mask::new(), &HashSet::new(), &mut summary);
+
+    // Place each contig
+    let place_contig = |q_str: &[u8], q_id: &str| -> (Option<Placement>, Vec<String>) {
+        let blocks = asm::find_blocks(q_id, q_str, &params, &index, opt.lookback, opt.min_seeds, opt.min_length);
+        place(q_str.len(), &blocks, opt.min_aligned, opt.conflict_fraction, opt.max_shift)
+    };
+    let mut placements = HashMap::<String, (Option<Placement>, Vec<String>)>::new();
+    let mut add_placement = |q_id: &str, found: &mut (Option<Placement>, Vec<String>)| {
+        placements.insert(q_id.to_string(), found.clone());
+        None::<()>
+    };
+    let start = Instant::now();
+    let fasta = is_fasta_filename(&opt.contigs);
+    let (buf, _) = get_reader(&opt.contigs);
+    if fasta {
+        let reader = seq_io::fasta::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fasta_records(reader, threads as u32, threads, |record, found| {*found = place_contig(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |record, found| {add_placement(record.id().unwrap(), found)}).ok();
+    }
+    else {
+        let reader = seq_io::fastq::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fastq_records(reader, threads as u32, threads, |record, found| {*found = place_contig(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |record, found| {add_placement(record.id().unwrap(), found)}).ok();
+    }
+    println!("Placed contigs in {:?}.", start.elapsed());
+
+    // Conflicts
+    let conflicts_filename = format!("{}.conflicts.tsv", opt.prefix);
+    let mut conflicts_file = match File::create(&conflicts_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", conflicts_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    writeln!(conflicts_file, "#contig\tplacement\taligned\tconflicts").expect("Error writing line.");
+    let mut conflicting = placements.iter().filter(|(_, (_, c))| !c.is_empty()).map(|(id, (p, c))| (id.clone(), p.clone(), c.join(","))).collect::<Vec<_>>();
+    conflicting.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    for (id, p, c) in conflicting.iter() {
+        let placement = match p {
+            Some(p) => format!("{}:{}-{}:{}\t{}", ref_map.get(&p.r_idx).unwrap().name, p.start.max(0), p.end, if p.rc {"-"} else {"+"}, p.aligned),
+            None => "unplaced\t0".to_string(),
+        };
+        writeln!(conflicts_file, "{}\t{}\t{}", id, placement, c).expect("Error writing line.");
+    }
+
+    // Order contigs along each chromosome (sequences are needed in that order, so they are all loaded)
+    let mut contigs = Vec::<(String, Vec<u8>)>::new();
+    closures::for_each_record(&opt.contigs, fasta, |id, _desc, seq| {contigs.push((id.to_string(), seq.to_vec()));});
+    let mut per_ref = HashMap::<usize, Vec<(Placement, usize)>>::new();
+    let mut unplaced = Vec::new();
+    for (i, (id, _)) in contigs.iter().enumerate() {
+        match placements.get(id) {
+            Some((Some(p), c)) if c.is_empty() || !opt.skip_conflicts => per_ref.entry(p.r_idx).or_insert_with(Vec::new).push((p.clone(), i)),
+            _ => unplaced.push(i),
+        }
+    }
+    let mut refs = per_ref.keys().cloned().collect::<Vec<usize>>();
+    refs.sort_unstable_by(|a, b| ref_map.get(a).unwrap().name.cmp(&ref_map.get(b).unwrap().name));
+
+    let agp_filename = format!("{}.agp", opt.prefix);
+    let fasta_filename = format!("{}.fasta", opt.prefix);
+    let mut agp_file = match File::create(&agp_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", agp_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let mut fasta_file = match File::create(&fasta_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", fasta_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    writeln!(agp_file, "##agp-version\t2.1").expect("Error writing line.");
+    let mut nb_placed = 0;
+    for r_idx in refs.iter() {
+        let object = format!("{}_scaffold", ref_map.get(r_idx).unwrap().name);
+        let placed = per_ref.get_mut(r_idx).unwrap();
+        placed.sort_unstable_by(|a, b| a.0.start.cmp(&b.0.start).then(b.0.end.cmp(&a.0.end)));
+        let mut seq = Vec::<u8>::new();
+        let mut part = 0;
+        let mut prev_end : Option<isize> = None;
+        for (p, i) in placed.iter() {
+            if let Some(prev_end) = prev_end {
+                let gap = p.start - prev_end;
+                let (gap_len, gap_type) = if gap >= UNKNOWN_GAP as isize {(gap as usize, "N")} else {(UNKNOWN_GAP, "U")};
+                part += 1;
+                writeln!(agp_file, "{}\t{}\t{}\t{}\t{}\t{}\tscaffold\tyes\talign_genus", object, seq.len() + 1, seq.len() + gap_len, part, gap_type, gap_len).expect("Error writing line.");
+                seq.extend(std::iter::repeat(b'N').take(gap_len));
+            }
+            let (id, contig) = &contigs[*i];
+            part += 1;
+            writeln!(agp_file, "{}\t{}\t{}\t{}\tW\t{}\t1\t{}\t{}", object, seq.len() + 1, seq.len() + contig.len(), part, id, contig.len(), if p.rc {"-"} else {"+"}).expect("Error writing line.");
+            if p.rc {seq.extend(dna::revcomp(contig));} else {seq.extend(contig);}
+            prev_end = Some(prev_end.map_or(p.end, |e| e.max(p.end)));
+            nb_placed += 1;
+        }
+        write_fasta(&mut fasta_file, &object, &seq);
+    }
+    for i in unplaced.iter() {
+        let (id, contig) = &contigs[*i];
+        writeln!(agp_file, "{}\t1\t{}\t1\tW\t{}\t1\t{}\t+", id, contig.len(), id, contig.len()).expect("Error writing line.");
+        write_fasta(&mut fasta_file, id, contig);
+    }
+    println!("Placed {} contigs on {} scaffolds, {} unplaced, {} with conflicting placements.", nb_placed, refs.len(), unplaced.len(), conflicting.len());
+    println!("Wrote {}, {} and {}.", agp_filename, fasta_filename, conflicts_filename);
+}
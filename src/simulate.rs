@@ -0,0 +1,179 @@
+// simulate.rs
+// Contains the "simulate" subcommand, which samples HiFi-like reads from a reference and writes them as FASTQ,
+// with true locations encoded in read names (see eval.rs).
+
+use crate::{closures::for_each_record, eval::truth_name, is_fasta_filename};
+use bio::alphabets::dna;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, LogNormal, Normal};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct SimulateOpt {
+    /// Reference genome to sample reads from (FASTA/FASTQ)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub reference: PathBuf,
+    /// Output FASTQ file
+    ///
+    #[structopt(parse(from_os_str), short, long, default_value = "simulated.fq")]
+    pub output: PathBuf,
+    /// Number of reads
+    ///
+    /// Overrides --depth if given.
+    #[structopt(long)]
+    pub nb_reads: Option<usize>,
+    /// Sequencing depth
+    ///
+    #[structopt(long, default_value = "10")]
+    pub depth: f64,
+    /// Mean read length (log-normal distribution)
+    ///
+    #[structopt(long, default_value = "24000")]
+    pub length_mean: f64,
+    /// Standard deviation of read length
+    ///
+    #[structopt(long, default_value = "3000")]
+    pub length_sd: f64,
+    /// Minimum read length
+    ///
+    #[structopt(long, default_value = "1000")]
+    pub min_length: usize,
+    /// Mean read identity
+    ///
+    #[structopt(long, default_value = "0.99")]
+    pub identity: f64,
+    /// Standard deviation of read identity
+    ///
+    #[structopt(long, default_value = "0.005")]
+    pub identity_sd: f64,
+    /// Fraction of errors that are indels (the rest are substitutions)
+    ///
+    #[structopt(long, default_value = "0.6")]
+    pub indel_fraction: f64,
+    /// Fraction of indels that are insertions
+    ///
+    #[structopt(long, default_value = "0.5")]
+    pub insertion_fraction: f64,
+    /// Error rate multiplier within homopolymers
+    ///
+    /// Bases in homopolymer runs of length >= 3 get
+    /// errors this many times more often; indels there
+    /// extend or shorten the run.
+    #[structopt(long, default_value = "4")]
+    pub homopolymer_bias: f64,
+    /// Strand of simulated reads (both, forward or reverse)
+    ///
+    #[structopt(long, default_value = "both")]
+    pub strand: String,
+    /// Random seed
+    ///
+    #[structopt(long, default_value = "42")]
+    pub seed: u64,
+}
+
+const BASES : [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+// Apply sequencing errors to a sequence, with an overall error rate of error_rate. Returns the read and the number of errors.
+pub fn add_errors(seq: &[u8], error_rate: f64, opt: &SimulateOpt, rng: &mut StdRng) -> (Vec<u8>, usize) {
+    // positions in homopolymer runs of length >= 3
+    let mut in_hp = vec![false; seq.len()];
+    let mut i = 0;
+    while i < seq.len() {
+        let mut j = i + 1;
+        while j < seq.len() && seq[j] == seq[i] {j += 1;}
+        if j - i >= 3 {in_hp[i..j].iter_mut().for_each(|x| *x = true);}
+        i = j;
+    }
+    let hp_frac = in_hp.iter().filter(|x| **x).count() as f64 / seq.len().max(1) as f64;
+    let base_rate = error_rate / (1.0 - hp_frac + hp_frac * opt.homopolymer_bias);
+    let mut read = Vec::with_capacity(seq.len() + seq.len() / 50);
+    let mut nb_errors = 0;
+    for (i, &c) in seq.iter().enumerate() {
+        let p = if in_hp[i] {base_rate * opt.homopolymer_bias} else {base_rate};
+        if rng.gen::<f64>() >= p {
+            read.push(c);
+            continue;
+        }
+        nb_errors += 1;
+        if rng.gen::<f64>() < opt.indel_fraction {
+            if rng.gen::<f64>() < opt.insertion_fraction {
+                let ins = if in_hp[i] {c} else {BASES[rng.gen_range(0..4)]};
+                read.push(ins);
+                read.push(c);
+            }
+            // else: deletion
+        }
+        else {
+            let mut sub = BASES[rng.gen_range(0..4)];
+            while sub == c {sub = BASES[rng.gen_range(0..4)];}
+            read.push(sub);
+        }
+    }
+    (read, nb_errors)
+}
+
+// Run the simulate subcommand.
+pub fn run(opt: &SimulateOpt) {
+    let mut refs = Vec::<(String, Vec<u8>)>::new();
+    for_each_record(&opt.reference, is_fasta_filename(&opt.reference), |id, _desc, seq| {
+        refs.push((id.to_string(), seq.to_vec()));
+    });
+    let total_len = refs.iter().map(|(_, s)| s.len()).sum::<usize>();
+    if total_len == 0 {panic!("Reference is empty.");}
+    let nb_reads = opt.nb_reads.unwrap_or(((opt.depth * total_len as f64) / opt.length_mean).ceil() as usize);
+    let (forward, reverse) = match opt.strand.as_str() {
+        "both" => (true, true),
+        "forward" | "fwd" | "+" => (true, false),
+        "reverse" | "rev" | "-" => (false, true),
+        _ => panic!("Unknown strand option: {} (expected both, forward or reverse).", opt.strand),
+    };
+    let sigma2 = (1.0 + (opt.length_sd * opt.length_sd) / (opt.length_mean * opt.length_mean)).ln();
+    let length_dist = LogNormal::new(opt.length_mean.ln() - sigma2 / 2.0, sigma2.sqrt()).expect("Invalid read length distribution.");
+    let identity_dist = Normal::new(opt.identity, opt.identity_sd).expect("Invalid identity distribution.");
+    let mut rng = StdRng::seed_from_u64(opt.seed);
+    let mut out = match File::create(&opt.output) {
+        Err(why) => panic!("Couldn't create {}: {}", opt.output.display(), why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let mut nb_written = 0;
+    let mut nb_attempts = 0;
+    let mut nb_bases = 0;
+    while nb_written < nb_reads {
+        nb_attempts += 1;
+        if nb_attempts > 100 * nb_reads + 1000 {panic!("Couldn't sample reads from the reference (too short, or too many Ns?).");}
+        let len = (length_dist.sample(&mut rng) as usize).max(opt.min_length);
+        // pick a reference with probability proportional to its length, then a position
+        let mut pos = rng.gen_range(0..total_len);
+        let mut r = 0;
+        while pos >= refs[r].1.len() {
+            pos -= refs[r].1.len();
+            r += 1;
+        }
+        let (r_id, r_seq) = &refs[r];
+        if len > r_seq.len() {continue;}
+        let start = rng.gen_range(0..=(r_seq.len() - len));
+        let end = start + len;
+        let fragment = &r_seq[start..end];
+        if fragment.iter().any(|c| !BASES.contains(c)) {continue;}
+        let rc = if forward && reverse {rng.gen_bool(0.5)} else {reverse};
+        let fragment = if rc {dna::revcomp(fragment)} else {fragment.to_vec()};
+        let identity = identity_dist.sample(&mut rng).clamp(0.5, 1.0);
+        let (read, nb_errors) = add_errors(&fragment, 1.0 - identity, opt, &mut rng);
+        let real_identity = 1.0 - nb_errors as f64 / len as f64;
+        let phred = if nb_errors == 0 {93} else {((-10.0 * (nb_errors as f64 / len as f64).log10()).round() as usize).min(93)};
+        let qual = vec![(phred + 33) as u8; read.len()];
+        let name = truth_name(&format!("S1_{}", nb_written + 1), r_id, start, end, rc);
+        writeln!(out, "@{} identity={:.4}", name, real_identity).expect("Error writing read.");
+        out.write_all(&read).expect("Error writing read.");
+        out.write_all(b"\n+\n").expect("Error writing read.");
+        out.write_all(&qual).expect("Error writing read.");
+        out.write_all(b"\n").expect("Error writing read.");
+        nb_written += 1;
+        nb_bases += read.len();
+    }
+    println!("Simulated {} reads ({} bases) to {}.", nb_written, nb_bases, opt.output.display());
+}
@@ -0,0 +1,226 @@
+// splice.rs
+// Spliced mapping of cDNA reads: refines the junctions between the exons of a mapping (see Chain::exons) at base level against the reference,
+// favouring canonical GT-AG motifs and annotated junctions, and reports the mapping as a CIGAR with N operations and as a BED12 transcript model.
+
+use crate::{closures, get_reader};
+use bio::alphabets::dna;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const JUNCTION_WINDOW: usize = 30; // number of query bases on each side of the chained exon ends where a junction is searched
+const MOTIF_BONUS: isize = 2; // score of a canonical splice motif, in mismatches
+const KNOWN_BONUS: isize = 10; // score of an annotated junction, in mismatches
+
+// Exon: query span (on the reference strand of the mapping) and reference span, half-open.
+type Exon = (usize, usize, usize, usize);
+
+// A reference sequence packed at 2 bits per base (a quarter of the memory of the sequence), with the intervals of other bases (N, IUPAC codes) kept apart.
+struct PackedSeq {
+    len: usize,
+    bits: Vec<u8>, // 4 bases per byte, low bits first
+    n_runs: Vec<(usize, usize)>, // half-open intervals of non-ACGT bases, sorted
+}
+impl PackedSeq {
+
+    // Pack an uppercase sequence.
+    fn new(seq: &[u8]) -> Self {
+        let mut bits = vec![0u8; (seq.len() + 3) / 4];
+        let mut n_runs : Vec<(usize, usize)> = Vec::new();
+        for (i, c) in seq.iter().enumerate() {
+            let code = match c {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => {
+                    match n_runs.last_mut() {
+                        Some(r) if r.1 == i => r.1 += 1,
+                        _ => n_runs.push((i, i + 1)),
+                    }
+                    0
+                }
+            };
+            bits[i / 4] |= code << (2 * (i % 4));
+        }
+        n_runs.shrink_to_fit();
+        PackedSeq {len: seq.len(), bits, n_runs}
+    }
+
+    // Base at position i (N for all non-ACGT bases).
+    fn get(&self, i: usize) -> u8 {
+        let r = self.n_runs.partition_point(|r| r.1 <= i);
+        if r < self.n_runs.len() && self.n_runs[r].0 <= i {return b'N';}
+        b"ACGT"[((self.bits[i / 4] >> (2 * (i % 4))) & 3) as usize]
+    }
+}
+
+pub struct Splice {
+    ref_seqs: HashMap<String, PackedSeq>, // reference sequences (2-bit packed), by name
+    known: HashSet<(String, usize, usize)>, // annotated introns: reference, start, end (0-based, half-open)
+    bed_file: Mutex<BufWriter<File>>, // BED12 transcript models
+}
+impl Splice {
+
+    // Load the reference sequences (2-bit packed: about a quarter of the reference size in memory) and the annotated junctions,
+    // and create <prefix>.splice.bed.
+    pub fn new(ref_filename: &PathBuf, ref_fasta: bool, gtf: Option<&PathBuf>, output_prefix: &str) -> Self {
+        let mut ref_seqs = HashMap::new();
+        closures::for_each_record(ref_filename, ref_fasta, |id, _, seq| {ref_seqs.insert(id.to_string(), PackedSeq::new(seq));});
+        let known = match gtf {Some(path) => load_gtf_junctions(path), None => HashSet::new()};
+        let bed_filename = format!("{}.splice.bed", output_prefix);
+        let bed_file = match File::create(&bed_filename) {
+            Err(why) => panic!("Couldn't create {}: {}", bed_filename, why),
+            Ok(f) => BufWriter::new(f),
+        };
+        Splice {ref_seqs, known, bed_file: Mutex::new(bed_file)}
+    }
+
+    // Refine the junctions of a mapping given its PAF line and exons (query order, forward query coordinates), write its transcript model,
+    // and return the PAF line with updated coordinates and cg:Z (CIGAR with N operations) and ts:A (transcript strand) tags.
+    // Supplementary lines following the first one are kept as they are.
+    pub fn process(&self, q_id: &str, q_str: &[u8], exons: &[Exon], line: &str) -> String {
+        let (first_line, rest) = match line.split_once('\n') {Some((f, r)) => (f, Some(r)), None => (line, None)};
+        let mut fields = first_line.split('\t').map(|s| s.to_string()).collect::<Vec<String>>();
+        if exons.is_empty() || fields.len() < 12 {return line.to_string();}
+        let rc = fields[4] == "-";
+        let r_name = fields[5].clone();
+        let r_str = match self.ref_seqs.get(&r_name) {Some(s) => s, None => return line.to_string()};
+        let mapq = fields[11].parse::<usize>().unwrap_or(0);
+        let q_len = q_str.len();
+
+        // exons and query on the reference strand (upper-cased, as reference sequences are)
+        let q_upper = q_str.to_ascii_uppercase();
+        let q_oriented = if rc {dna::revcomp(&q_upper)} else {q_upper};
+        let mut exons = exons.to_vec();
+        if rc {
+            exons.reverse();
+            for e in exons.iter_mut() {*e = (q_len - e.1, q_len - e.0, e.2, e.3);}
+        }
+
+        // extend the terminal exons to the query ends
+        let first = exons[0];
+        let ext = first.0.min(first.2);
+        exons[0] = (first.0 - ext, first.1, first.2 - ext, first.3);
+        let last = exons[exons.len() - 1];
+        let ext = (q_len - last.1).min(r_str.len - last.3);
+        let n = exons.len();
+        exons[n - 1] = (last.0, last.1 + ext, last.2, last.3 + ext);
+
+        let mut strands = Vec::new();
+        for i in 1..exons.len() {
+            let (j, d1, d2, strand) = self.best_junction(&r_name, r_str, &q_oriented, &exons[i - 1], &exons[i]);
+            exons[i - 1].1 = j;
+            exons[i - 1].3 = (j as isize + d1) as usize;
+            exons[i].0 = j;
+            exons[i].2 = (j as isize + d2) as usize;
+            if let Some(s) = strand {strands.push(s);}
+        }
+
+        let plus = strands.iter().filter(|s| **s == '+').count();
+        let ts = if strands.is_empty() {None} else if plus * 2 >= strands.len() {Some('+')} else {Some('-')};
+        let (q_start, q_end) = (exons[0].0, exons[exons.len() - 1].1);
+        let (q_start, q_end) = if rc {(q_len - q_end, q_len - q_start)} else {(q_start, q_end)};
+        let (r_start, r_end) = (exons[0].2, exons[exons.len() - 1].3);
+        fields[2] = q_start.to_string();
+        fields[3] = (q_end - 1).to_string();
+        fields[7] = r_start.to_string();
+        fields[8] = (r_end - 1).to_string();
+        let mut out = fields.join("\t");
+        out.push_str(&format!("\tcg:Z:{}", cigar(&exons)));
+        if let Some(s) = ts {out.push_str(&format!("\tts:A:{}", s));}
+        if let Some(r) = rest {out.push('\n'); out.push_str(r);}
+
+        let sizes = exons.iter().map(|e| format!("{},", e.3 - e.2)).collect::<String>();
+        let starts = exons.iter().map(|e| format!("{},", e.2 - r_start)).collect::<String>();
+        let bed_line = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t0\t{}\t{}\t{}\n", r_name, r_start, r_end, q_id, mapq.min(1000), ts.unwrap_or('.'), r_start, r_end, exons.len(), sizes, starts);
+        self.bed_file.lock().unwrap().write_all(bed_line.as_bytes()).expect("Error writing splice BED line.");
+        out
+    }
+
+    // Best junction between two consecutive exons: the query position j where the first exon ends and the second one starts, and the offsets
+    // from j to the intron start and end on the reference (diagonals of the two exons), plus the strand of the splice motif if it is canonical.
+    // Candidates are scored by mismatches of the query against both diagonals around the exon ends, with bonuses for motifs and annotated junctions.
+    fn best_junction(&self, r_name: &str, r_str: &PackedSeq, q_str: &[u8], a: &Exon, b: &Exon) -> (usize, isize, isize, Option<char>) {
+        let d1 = a.3 as isize - a.1 as isize;
+        let d2 = b.2 as isize - b.0 as isize;
+        // keep both exons non-empty on the query and the reference
+        let lo = a.1.saturating_sub(JUNCTION_WINDOW).max(a.0 + 1).max((a.2 as isize - d1 + 1).max(0) as usize);
+        let hi = (b.0 + JUNCTION_WINDOW).min(b.1 - 1).min((b.3 as isize - d2 - 1).max(0) as usize);
+        let hi = hi.min((r_str.len as isize - d1).max(0) as usize);
+        if lo > hi || d2 <= d1 {return (a.1, d1, d2, None);}
+        let base = |p: isize| if p >= 0 && (p as usize) < r_str.len {r_str.get(p as usize)} else {b'N'};
+        // prefix sums of mismatches against the first diagonal, suffix sums against the second one
+        let mut m1 = vec![0isize; hi - lo + 1];
+        let mut m2 = vec![0isize; hi - lo + 1];
+        for i in lo..hi {
+            m1[i - lo + 1] = m1[i - lo] + (q_str[i] != base(i as isize + d1)) as isize;
+        }
+        for i in (lo..hi).rev() {
+            m2[i - lo] = m2[i - lo + 1] + (q_str[i] != base(i as isize + d2)) as isize;
+        }
+        let mid = (a.1 + b.0) / 2;
+        let mut best : Option<(isize, usize, usize, Option<char>)> = None;
+        for j in lo..=hi {
+            let (start, end) = (j as isize + d1, j as isize + d2);
+            let donor = [base(start), base(start + 1)];
+            let acceptor = [base(end - 2), base(end - 1)];
+            let strand = match (&donor, &acceptor) {
+                (b"GT", b"AG") => Some('+'),
+                (b"CT", b"AC") => Some('-'),
+                _ => None,
+            };
+            let mut score = -(m1[j - lo] + m2[j - lo]);
+            if strand.is_some() {score += MOTIF_BONUS;}
+            if self.known.contains(&(r_name.to_string(), start as usize, end as usize)) {score += KNOWN_BONUS;}
+            let dist = (j as isize - mid as isize).unsigned_abs();
+            if best.map_or(true, |(s, d, _, _)| score > s || (score == s && dist < d)) {best = Some((score, dist, j, strand));}
+        }
+        let (_, _, j, strand) = best.unwrap();
+        (j, d1, d2, strand)
+    }
+}
+
+// CIGAR of a spliced mapping: each exon is aligned ungapped on its shorter side, with an insertion or deletion for the length difference,
+// and consecutive exons are separated by an N operation (and an insertion for any query bases between them).
+fn cigar(exons: &[Exon]) -> String {
+    let mut cigar = String::new();
+    for (i, e) in exons.iter().enumerate() {
+        if i > 0 {
+            let p = exons[i - 1];
+            if e.0 > p.1 {cigar.push_str(&format!("{}I", e.0 - p.1));}
+            cigar.push_str(&format!("{}N", e.2 - p.3));
+        }
+        let (q, r) = (e.1 - e.0, e.3 - e.2);
+        cigar.push_str(&format!("{}M", q.min(r)));
+        if q > r {cigar.push_str(&format!("{}I", q - r));}
+        else if r > q {cigar.push_str(&format!("{}D", r - q));}
+    }
+    cigar
+}
+
+// Introns of the transcripts of a GTF annotation (0-based, half-open): gaps between consecutive "exon" features of each transcript_id.
+pub fn load_gtf_junctions(path: &PathBuf) -> HashSet<(String, usize, usize)> {
+    let (buf, _) = get_reader(path);
+    let mut exons = HashMap::<(String, String), Vec<(usize, usize)>>::new();
+    for line in buf.lines() {
+        let line = line.expect("Error reading GTF file.");
+        if line.is_empty() || line.starts_with('#') {continue;}
+        let fields : Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 || fields[2] != "exon" {continue;}
+        let start = fields[3].parse::<usize>().expect("Invalid start coordinate in GTF file.");
+        let end = fields[4].parse::<usize>().expect("Invalid end coordinate in GTF file.");
+        let transcript = fields[8].split(';').find_map(|a| a.trim().strip_prefix("transcript_id ")).unwrap_or_else(|| panic!("GTF exon without transcript_id: {}", line));
+        exons.entry((fields[0].to_string(), transcript.trim_matches('"').to_string())).or_insert_with(Vec::new).push((start - 1, end));
+    }
+    let mut junctions = HashSet::new();
+    for ((r_name, _), mut e) in exons {
+        e.sort_unstable();
+        for w in e.windows(2) {
+            if w[1].0 > w[0].1 {junctions.insert((r_name.clone(), w[0].1, w[1].0));}
+        }
+    }
+    junctions
+}
@@ -1,77 +1,151 @@
 // stats.rs
-// Contains the "Stats" struct, to collect statistics on the potential reference locations of reads
+// Contains the "Stats" struct, to collect seeding and chaining statistics on each read, and the "StatsWriter" struct, to write them as TSV
 //
-// heuristic: collect all potential reference locations given by the kminmers of the read, 
-// sort those locations, find the number of 'jumps' as defined by two consecutive locations whose 
+// loci heuristic: collect all potential reference locations given by the kminmers of the read,
+// sort those locations, find the number of 'jumps' as defined by two consecutive locations whose
 // distance is > 2x read length
 
-use std::{fs::File, io::Write, mem::MaybeUninit, sync::Mutex};
-use crate::{Entry};
+use std::{fs::File, io::{BufWriter, Write}, sync::Mutex};
+use crate::{Entry, chain::{Chain, Indel, Inversion}, r#match::Match};
 use fxhash::{hash32};
 
-static ENABLED: bool = false;
+// Outcome of mapping a read
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Status {
+    #[default]
+    NoKminmers, // read too short, or too few minimizers to form a k-min-mer
+    NoHits, // no k-min-mer found in the Index
+    RepeatsOnly, // k-min-mers only hit repeated k-min-mers of the Index
+    NoChain, // no valid chain on any reference
+    Ambiguous, // equally good chains on several references
+    Decoy, // best placement on a decoy reference (not reported)
+    Mapped,
+}
+
+impl Status {
+    pub const UNMAPPED: [Status; 6] = [Status::NoKminmers, Status::NoHits, Status::RepeatsOnly, Status::NoChain, Status::Ambiguous, Status::Decoy];
 
-// some rust black magic to have multithreaded static file handle
-// from https://stackoverflow.com/a/72187853
-static mut STATS_FILE: MaybeUninit<Mutex<File>> = MaybeUninit::uninit();
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::NoKminmers => "no_kminmers",
+            Status::NoHits => "no_hits",
+            Status::RepeatsOnly => "repeats_only",
+            Status::NoChain => "no_chain",
+            Status::Ambiguous => "ambiguous",
+            Status::Decoy => "decoy",
+            Status::Mapped => "mapped",
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct Stats {
     pub q_id: String,
+    pub q_len: usize,
+    pub nb_kminmers: usize, // k-min-mers extracted from the read
+    pub nb_hits: usize, // k-min-mers hitting a unique Entry of the Index
+    pub nb_repeats: usize, // k-min-mers hitting a repeated (empty) Entry of the Index
+    pub nb_hap1: usize, // k-min-mers hitting an Entry specific to haplotype 1
+    pub nb_hap2: usize, // k-min-mers hitting an Entry specific to haplotype 2
+    pub nb_refs: usize, // candidate references (with at least one Match)
+    pub nb_matches: usize, // Matches over all candidate references
+    pub nb_chains: usize, // candidate references yielding a chain
+    pub score: usize, // number of k-min-mer matches in the best chain
+    pub mapq: usize, // mapping quality of the reported mapping
+    pub nb_loci: usize, // number of distinct reference loci (see heuristic above)
+    pub status: Status, // outcome of mapping
+    pub r_idx: usize, // reference of the reported mapping
+    pub r_spans: Vec<(usize, usize)>, // half-open reference intervals covered by the reported mapping
+    pub indels: Vec<Indel>, // large indels within the reported mapping
+    pub inversions: Vec<Inversion>, // inverted segments within the reported mapping
+    pub exons: Vec<(usize, usize, usize, usize)>, // query and reference spans of the exons of the reported mapping, in query order (spliced mode)
     pub ref_loci: Vec<(u32,usize)>, // reference locis for analyzed read
+    pub anchors: Vec<(usize, Match, bool)>, // reference, Match, and whether it was kept in the Chain (only recorded for dot plots)
+    pub record_anchors: bool, // whether Matches are recorded in anchors
+    enabled: bool, // whether reference loci are collected and the read is written to the stats file
 }
 
 impl Stats {
 
-    pub fn init(nb_threads: usize, output_prefix: &str)
-    {
-        if ENABLED
+    // A new Stats object for a read
+    pub fn new(q_id: &str, q_len: usize, enabled: bool) -> Self {
+        if enabled
         {
-            if nb_threads != 1
-            {
-                //panic!("If stats are enabled, mapquik can only be run in 1 thread");
-                //seems code works with multithreads..
+            Stats {
+                q_id: q_id.to_string(),
+                q_len,
+                enabled,
+                ..Default::default()
             }
+        }
+        else { Stats { q_len, ..Default::default() } }
+    }
 
-            // Stats file generation
-            let stats_path = format!("{}{}", output_prefix, ".read_stats");
-            unsafe
+    // Count a k-min-mer extracted from the read
+    pub fn add_kminmer(&mut self)
+    {
+        self.nb_kminmers += 1;
+    }
+
+    // Count the result of an Index lookup (a unique Entry, a repeated Entry, or nothing)
+    pub fn add_lookup(&mut self, e: Option<&Entry>)
+    {
+        if let Some(r) = e
+        {
+            if r.is_empty() { self.nb_repeats += 1; }
+            else
             {
-            STATS_FILE = match File::create(&stats_path) {
-                Err(why) => panic!("Couldn't create {}: {}", stats_path, why.to_string()),
-                Ok(stats_file) =>  MaybeUninit::new(Mutex::new(stats_file)),
-            };
+                self.nb_hits += 1;
+                match r.hap {
+                    1 => self.nb_hap1 += 1,
+                    2 => self.nb_hap2 += 1,
+                    _ => {}
+                }
+                self.add(r);
             }
-            println!("Stats module initialized.");
         }
     }
 
-    // A new Stats object for a read
-    pub fn new(q_id: &str) -> Self {
-        if ENABLED
+    // Haplotype the read is assigned to: the one with more specific k-min-mers (0 if tied)
+    pub fn haplotype(&self) -> usize
+    {
+        if self.nb_hap1 > self.nb_hap2 { 1 }
+        else if self.nb_hap2 > self.nb_hap1 { 2 }
+        else { 0 }
+    }
+
+    // Record the Matches on a reference, and whether each of them survived chaining
+    pub fn add_anchors(&mut self, r_idx: usize, matches: &[Match], chain: &Chain)
+    {
+        if self.record_anchors
         {
-            let s = Stats {
-                q_id: q_id.to_string(),
-                ref_loci: vec![]
-            };
-            s
+            for h in matches.iter() { self.anchors.push((r_idx, h.clone(), chain.contains(h))); }
         }
-        else { Stats::default() }
     }
-    
+
+    // Set the reason why the read is not mapped, given the number of references yielding a chain
+    pub fn set_unmapped(&mut self, coords_count: usize)
+    {
+        self.status = if self.nb_kminmers == 0 { Status::NoKminmers }
+        else if self.nb_hits == 0 && self.nb_repeats == 0 { Status::NoHits }
+        else if self.nb_hits == 0 { Status::RepeatsOnly }
+        else if coords_count == 0 { Status::NoChain }
+        else { Status::Ambiguous };
+    }
+
     // Add a new potential reference location
     pub fn add(&mut self, r: &Entry)
     {
-        if ENABLED
+        if self.enabled
         {
             self.ref_loci.push((hash32(&r.id),r.start));
         }
     }
 
-    // Compute number of jumps and write to stats file
+    // Compute number of jumps
     pub fn finalize(&mut self)
     {
-        if ENABLED
+        if self.enabled
         {
             self.ref_loci.sort();
             let mut prev: (u32, usize) = (0,0);
@@ -85,12 +159,37 @@ impl Stats {
                 }
                 prev = (*a,*b);
             }
-            let stats_line = format!("{}: {}",self.q_id,nb_loci);
-            unsafe
-            {
-            write!(STATS_FILE.assume_init_mut().lock().unwrap(), "{}\n", stats_line).expect("Error writing stats line.");
-            }
+            self.nb_loci = nb_loci;
         }
     }
 
 }
+
+// A StatsWriter object holds the per-read stats file, shared by all mapping threads
+pub struct StatsWriter {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl StatsWriter {
+
+    // Create the stats file and write its header
+    pub fn new(output_prefix: &str) -> Self
+    {
+        let stats_path = format!("{}{}", output_prefix, ".read_stats.tsv");
+        let mut stats_file = match File::create(&stats_path) {
+            Err(why) => panic!("Couldn't create {}: {}", stats_path, why),
+            Ok(stats_file) => BufWriter::new(stats_file),
+        };
+        writeln!(stats_file, "read_id\tread_len\tkminmers\thits\trepeat_hits\tcandidate_refs\tmatches\tchains\tscore\tmapq\tloci\tstatus").expect("Error writing stats header.");
+        println!("Writing per-read statistics to {}.", stats_path);
+        StatsWriter { file: Mutex::new(stats_file) }
+    }
+
+    // Finalize the Stats of a read and write them as a TSV line
+    pub fn write(&self, stats: &mut Stats)
+    {
+        stats.finalize();
+        let stats_line = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", stats.q_id, stats.q_len, stats.nb_kminmers, stats.nb_hits, stats.nb_repeats, stats.nb_refs, stats.nb_matches, stats.nb_chains, stats.score, stats.mapq, stats.nb_loci, stats.status.name());
+        writeln!(self.file.lock().unwrap(), "{}", stats_line).expect("Error writing stats line.");
+    }
+}
@@ -0,0 +1,144 @@
+// summary.rs
+// Contains the "Summary" struct, which aggregates the Stats of all reads of a run (with atomic counters shared by mapping threads), and writes a JSON report.
+
+use crate::{Params, get_memory_rusage, index::RefInfo, stats::{Stats, Status}};
+use dashmap::DashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+pub const MAX_MAPQ: usize = 60;
+pub const LEN_BIN: usize = 2000; // read length histogram bin size
+pub const NB_LEN_BINS: usize = 51; // the last bin holds all longer reads
+pub const SEED_BIN: usize = 10; // seed hits histogram bin size
+pub const NB_SEED_BINS: usize = 51; // the last bin holds all reads with more seed hits
+
+pub struct Summary {
+    pub nb_reads: AtomicUsize, // reads processed
+    pub nb_bases: AtomicUsize, // bases processed
+    pub nb_mapped: AtomicUsize, // reads with a reported mapping
+    pub nb_bases_mapped: AtomicUsize, // bases of reads with a reported mapping
+    pub mapq: Vec<AtomicUsize>, // number of mapped reads per MAPQ (capped at MAX_MAPQ)
+    pub unmapped: Vec<AtomicUsize>, // number of unmapped reads per reason (see Status::UNMAPPED)
+    pub reads_per_ref: DashMap<usize, usize>, // number of mapped reads per reference
+    pub len_hist: Vec<AtomicUsize>, // number of reads per length bin
+    pub len_hist_mapped: Vec<AtomicUsize>, // number of mapped reads per length bin
+    pub seeds_hist: Vec<AtomicUsize>, // number of reads per bin of k-min-mers hitting a unique Entry of the Index
+    pub nb_unique: usize, // unique k-min-mers in the Index
+    pub nb_repeated: usize, // repeated k-min-mers in the Index
+    pub index_duration: Duration,
+    pub map_duration: Duration,
+}
+
+impl Summary {
+
+    // A new, empty Summary.
+    pub fn new() -> Self {
+        Summary {
+            nb_reads: AtomicUsize::new(0),
+            nb_bases: AtomicUsize::new(0),
+            nb_mapped: AtomicUsize::new(0),
+            nb_bases_mapped: AtomicUsize::new(0),
+            mapq: (0..=MAX_MAPQ).map(|_| AtomicUsize::new(0)).collect(),
+            unmapped: Status::UNMAPPED.iter().map(|_| AtomicUsize::new(0)).collect(),
+            reads_per_ref: DashMap::new(),
+            len_hist: (0..NB_LEN_BINS).map(|_| AtomicUsize::new(0)).collect(),
+            len_hist_mapped: (0..NB_LEN_BINS).map(|_| AtomicUsize::new(0)).collect(),
+            seeds_hist: (0..NB_SEED_BINS).map(|_| AtomicUsize::new(0)).collect(),
+            nb_unique: 0,
+            nb_repeated: 0,
+            index_duration: Duration::ZERO,
+            map_duration: Duration::ZERO,
+        }
+    }
+
+    // Record the outcome of a read (called from mapping threads).
+    pub fn add(&self, stats: &Stats) {
+        self.nb_reads.fetch_add(1, Ordering::Relaxed);
+        self.nb_bases.fetch_add(stats.q_len, Ordering::Relaxed);
+        let len_bin = (stats.q_len / LEN_BIN).min(NB_LEN_BINS - 1);
+        self.len_hist[len_bin].fetch_add(1, Ordering::Relaxed);
+        self.seeds_hist[(stats.nb_hits / SEED_BIN).min(NB_SEED_BINS - 1)].fetch_add(1, Ordering::Relaxed);
+        if stats.status == Status::Mapped {
+            self.len_hist_mapped[len_bin].fetch_add(1, Ordering::Relaxed);
+            self.nb_mapped.fetch_add(1, Ordering::Relaxed);
+            self.nb_bases_mapped.fetch_add(stats.q_len, Ordering::Relaxed);
+            self.mapq[stats.mapq.min(MAX_MAPQ)].fetch_add(1, Ordering::Relaxed);
+            *self.reads_per_ref.entry(stats.r_idx).or_insert(0) += 1;
+        }
+        else {
+            let i = Status::UNMAPPED.iter().position(|s| *s == stats.status).unwrap();
+            self.unmapped[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Number of unmapped reads for a given reason.
+    pub fn get_unmapped(&self, status: Status) -> usize {
+        let i = Status::UNMAPPED.iter().position(|s| *s == status).unwrap();
+        self.unmapped[i].load(Ordering::Relaxed)
+    }
+
+    // Write the Summary, along with the parameters used and per-reference read counts, to <prefix>.summary.json.
+    pub fn write_json(&self, output_prefix: &str, params: &Params, ref_map: &DashMap<usize, RefInfo>) {
+        let summary_path = format!("{}{}", output_prefix, ".summary.json");
+        let mut f = match File::create(&summary_path) {
+            Err(why) => panic!("Couldn't create {}: {}", summary_path, why),
+            Ok(f) => BufWriter::new(f),
+        };
+        let nb_reads = self.nb_reads.load(Ordering::Relaxed);
+        let nb_bases = self.nb_bases.load(Ordering::Relaxed);
+        let nb_mapped = self.nb_mapped.load(Ordering::Relaxed);
+        let nb_refs = ref_map.len();
+        let ref_bases = ref_map.iter().map(|r| r.len).sum::<usize>();
+        let index_s = self.index_duration.as_secs_f64();
+        let map_s = self.map_duration.as_secs_f64();
+        let per_s = |n: usize, s: f64| if s > 0.0 {n as f64 / s} else {0.0};
+        let mut json = String::new();
+        json.push_str("{\n");
+        json.push_str(&format!("  \"version\": \"{}\",\n", env!("CARGO_PKG_VERSION")));
+        json.push_str(&format!("  \"parameters\": {{\"k\": {}, \"l\": {}, \"density\": {}, \"hpc\": {}, \"simd\": {}, \"min_chain_length\": {}, \"min_seeds\": {}, \"max_gap_diff\": {}}},\n",
+                               params.k, params.l, params.density, params.use_hpc, params.use_simd, params.c, params.s, params.g));
+        json.push_str(&format!("  \"index\": {{\"references\": {}, \"reference_bases\": {}, \"unique_kminmers\": {}, \"repeated_kminmers\": {}, \"time_s\": {:.3}, \"bases_per_s\": {:.1}}},\n",
+                               nb_refs, ref_bases, self.nb_unique, self.nb_repeated, index_s, per_s(ref_bases, index_s)));
+        json.push_str(&format!("  \"mapping\": {{\"reads\": {}, \"bases\": {}, \"mapped\": {}, \"unmapped\": {}, \"bases_mapped\": {}, \"time_s\": {:.3}, \"reads_per_s\": {:.1}, \"bases_per_s\": {:.1}}},\n",
+                               nb_reads, nb_bases, nb_mapped, nb_reads - nb_mapped, self.nb_bases_mapped.load(Ordering::Relaxed), map_s, per_s(nb_reads, map_s), per_s(nb_bases, map_s)));
+        let unmapped = Status::UNMAPPED.iter().map(|s| format!("\"{}\": {}", s.name(), self.get_unmapped(*s))).collect::<Vec<String>>().join(", ");
+        json.push_str(&format!("  \"unmapped_by_reason\": {{{}}},\n", unmapped));
+        let mapq = (0..=MAX_MAPQ).filter_map(|q| {
+            let n = self.mapq[q].load(Ordering::Relaxed);
+            if n > 0 {Some(format!("\"{}\": {}", q, n))} else {None}
+        }).collect::<Vec<String>>().join(", ");
+        json.push_str(&format!("  \"mapq\": {{{}}},\n", mapq));
+        let mut ref_ids = ref_map.iter().map(|r| *r.key()).collect::<Vec<usize>>();
+        ref_ids.sort_unstable();
+        let refs = ref_ids.iter().map(|i| {
+            let r = ref_map.get(i).unwrap();
+            let nb = self.reads_per_ref.get(i).map_or(0, |n| *n);
+            format!("    {{\"name\": {}, \"length\": {}, \"reads\": {}}}", json_string(&r.name), r.len, nb)
+        }).collect::<Vec<String>>().join(",\n");
+        json.push_str(&format!("  \"references\": [\n{}\n  ],\n", refs));
+        json.push_str(&format!("  \"max_rss_bytes\": {}\n", get_memory_rusage()));
+        json.push_str("}\n");
+        f.write_all(json.as_bytes()).expect("Error writing summary.");
+        println!("Wrote run summary to {}.", summary_path);
+    }
+}
+
+// Quote and escape a string for JSON output.
+pub fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
@@ -0,0 +1,313 @@
+// sv.rs
+// Contains the "sv" subcommand, which calls structural variant candidates from split-read evidence: reads are mapped as colinear segments,
+// and consecutive segments of a read that cannot be chained (gap length difference above the SV size, strand flip, other chromosome)
+// give breakpoints, which are clustered across reads and written as VCF.
+
+use crate::{asm::{self, Block}, closures, get_reader, index::RefInfo, is_fasta_filename, mask::Mask, presets::SeedingOpt, summary::Summary};
+use dashmap::DashMap;
+use seq_io::BaseRecord;
+use seq_io::parallel::{read_process_fasta_records, read_process_fastq_records};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+const LOOKBACK: usize = 50; // preceding matches considered when chaining a match into a read segment
+
+#[derive(Debug, StructOpt)]
+pub struct SvOpt {
+    /// Reference genome input
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub reference: PathBuf,
+    /// Reads (FASTA/FASTQ, raw or compressed)
+    ///
+    #[structopt(parse(from_os_str))]
+    pub reads: PathBuf,
+    /// Output prefix for VCF file
+    ///
+    #[structopt(short, long, default_value = "mapquik-sv")]
+    pub prefix: String,
+    #[structopt(flatten)]
+    pub seeding: SeedingOpt,
+    /// Minimum SV length
+    ///
+    /// Also the maximum gap length difference between chained
+    /// matches (unless --gap-diff is given), so that larger
+    /// gaps split read segments.
+    #[structopt(long, default_value = "50")]
+    pub min_sv_length: usize,
+    /// Minimum number of matching k-min-mers in a read segment
+    ///
+    #[structopt(long, default_value = "3")]
+    pub min_seeds: usize,
+    /// Minimum read segment length
+    ///
+    #[structopt(long, default_value = "500")]
+    pub min_length: usize,
+    /// Maximum distance between breakpoints of a cluster
+    ///
+    #[structopt(long, default_value = "500")]
+    pub cluster_distance: usize,
+    /// Minimum number of reads supporting a call
+    ///
+    #[structopt(long, default_value = "3")]
+    pub min_support: usize,
+}
+
+// Type of a structural variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvType {
+    Del,
+    Ins,
+    Inv,
+    Dup,
+    Bnd,
+}
+
+impl SvType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SvType::Del => "DEL",
+            SvType::Ins => "INS",
+            SvType::Inv => "INV",
+            SvType::Dup => "DUP",
+            SvType::Bnd => "BND",
+        }
+    }
+}
+
+// A breakpoint observed in one read, between two consecutive segments a and b of the read.
+// pos1 is on r1 where the read leaves a, pos2 on r2 where it enters b (0-based); len is the SV length (0 for INV and BND).
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub sv_type: SvType,
+    pub r1: usize,
+    pub pos1: usize,
+    pub r2: usize,
+    pub pos2: usize,
+    pub len: usize,
+    pub rc1: bool, // strand of segment a
+    pub rc2: bool, // strand of segment b
+    pub read: usize, // read index
+}
+
+// Breakpoints between consecutive segments of a read (sorted by query position).
+pub fn read_breakpoints(segments: &[Block], min_sv_length: usize, read: usize) -> Vec<Breakpoint> {
+    let mut breakpoints = Vec::new();
+    for w in segments.windows(2) {
+        let (a, b) = (&w[0], &w[1]);
+        let (pos1, pos2) = (a.exit_pos(), b.entry_pos());
+        let bp = |sv_type, pos1, pos2, len| Breakpoint {sv_type, r1: a.r_idx, pos1, r2: b.r_idx, pos2, len, rc1: a.rc, rc2: b.rc, read};
+        if a.r_idx != b.r_idx {breakpoints.push(bp(SvType::Bnd, pos1, pos2, 0)); continue;}
+        if a.rc != b.rc {breakpoints.push(bp(SvType::Inv, pos1.min(pos2), pos1.max(pos2), 0)); continue;}
+        // distances along the read direction
+        let q_dist = b.q_start as isize - a.q_end as isize;
+        let r_dist = if a.rc {pos1 as isize - pos2 as isize} else {pos2 as isize - pos1 as isize};
+        let (lo, hi) = (pos1.min(pos2), pos1.max(pos2));
+        if r_dist <= -(min_sv_length as isize) {breakpoints.push(bp(SvType::Dup, lo, hi, (-r_dist) as usize));}
+        else if r_dist - q_dist >= min_sv_length as isize {breakpoints.push(bp(SvType::Del, lo, lo + (r_dist - q_dist) as usize, (r_dist - q_dist) as usize));}
+        else if q_dist - r_dist >= min_sv_length as isize {breakpoints.push(bp(SvType::Ins, lo, lo, (q_dist - r_dist) as usize));}
+    }
+    breakpoints
+}
+
+// Cluster breakpoints of the same type and references whose positions (and lengths) are within max_dist, and return clusters of at least
+// min_support distinct reads.
+pub fn cluster_breakpoints(breakpoints: &mut [Breakpoint], max_dist: usize, min_support: usize) -> Vec<Vec<Breakpoint>> {
+    breakpoints.sort_unstable_by_key(|b| (b.sv_type, b.r1, b.r2, b.rc1, b.rc2, b.pos1));
+    let mut clusters : Vec<Vec<Breakpoint>> = Vec::new();
+    let mut current : Vec<Breakpoint> = Vec::new();
+    let close = |a: &Breakpoint, b: &Breakpoint| a.sv_type == b.sv_type && a.r1 == b.r1 && a.r2 == b.r2 && a.rc1 == b.rc1 && a.rc2 == b.rc2
+        && b.pos1 - a.pos1 <= max_dist && a.pos2.abs_diff(b.pos2) <= max_dist && a.len.abs_diff(b.len) <= max_dist.max(a.len / 10);
+    for b in breakpoints.iter() {
+        if current.last().map_or(false, |l| !close(&current[0], b) || !close(l, b)) {
+            clusters.push(std::mem::take(&mut current));
+        }
+        current.push(b.clone());
+    }
+    if !current.is_empty() {clusters.push(current);}
+    clusters.retain(|c| c.iter().map(|b| b.read).collect::<HashSet<usize>>().len() >= min_support);
+    clusters
+}
+
+fn median(mut v: Vec<usize>) -> usize {
+    v.sort_unstable();
+    v[v.len() / 2]
+}
+
+// VCF ALT of a breakend on r1 at pos1 (1-based) joined to r2 at pos2, given the strands of the read segments before and after the junction.
+fn bnd_alt(rc1: bool, rc2: bool, r2: &str, pos2: usize) -> String {
+    match (rc1, rc2) {
+        (false, false) => format!("N[{}:{}[", r2, pos2),
+        (false, true) => format!("N]{}:{}]", r2, pos2),
+        (true, true) => format!("]{}:{}]N", r2, pos2),
+        (true, false) => format!("[{}:{}[N", r2, pos2),
+    }
+}
+
+// VCF record(s) of a cluster (two mate records for a BND).
+pub fn vcf_records(id: usize, cluster: &[Breakpoint], ref_map: &DashMap<usize, RefInfo>) -> Vec<String> {
+    let b = &cluster[0];
+    let support = cluster.iter().map(|b| b.read).collect::<HashSet<usize>>().len();
+    let pos1 = median(cluster.iter().map(|b| b.pos1).collect());
+    let pos2 = median(cluster.iter().map(|b| b.pos2).collect());
+    let len = median(cluster.iter().map(|b| b.len).collect());
+    let r1 = ref_map.get(&b.r1).unwrap().name.clone();
+    let r2 = ref_map.get(&b.r2).unwrap().name.clone();
+    let name = b.sv_type.name();
+    // 1-based positions (END - POS is the reference span of the event)
+    let (vcf_pos1, vcf_pos2) = (pos1 + 1, pos2 + 1);
+    match b.sv_type {
+        SvType::Bnd => vec![
+            format!("{}\t{}\tmapquik.BND.{}_1\tN\t{}\t.\tPASS\tSVTYPE=BND;MATEID=mapquik.BND.{}_2;SUPPORT={}", r1, vcf_pos1, id, bnd_alt(b.rc1, b.rc2, &r2, vcf_pos2), id, support),
+            format!("{}\t{}\tmapquik.BND.{}_2\tN\t{}\t.\tPASS\tSVTYPE=BND;MATEID=mapquik.BND.{}_1;SUPPORT={}", r2, vcf_pos2, id, bnd_alt(!b.rc2, !b.rc1, &r1, vcf_pos1), id, support),
+        ],
+        SvType::Ins => vec![format!("{}\t{}\tmapquik.INS.{}\tN\t<INS>\t.\tPASS\tSVTYPE=INS;END={};SVLEN={};SUPPORT={}", r1, vcf_pos1, id, vcf_pos1, len, support)],
+        SvType::Del => vec![format!("{}\t{}\tmapquik.DEL.{}\tN\t<DEL>\t.\tPASS\tSVTYPE=DEL;END={};SVLEN=-{};SUPPORT={}", r1, vcf_pos1, id, vcf_pos1 + len, len, support)],
+        _ => vec![format!("{}\t{}\tmapquik.{}.{}\tN\t<{}>\t.\tPASS\tSVTYPE={};END={};SVLEN={};SUPPORT={}", r1, vcf_pos1, name, id, name, name, vcf_pos2.max(vcf_pos1), pos2.saturating_sub(pos1), support)],
+    }
+}
+
+// Run the sv subcommand.
+pub fn run(opt: &SvOpt) {
+    let mut params = opt.seeding.to_params("hifi");
+    let threads = opt.seeding.preset.threads;
+    if opt.min_sv_length == 0 {panic!("--min-sv-length must be at least 1.");}
+    params.g = opt.seeding.preset.gap_diff.unwrap_or(opt.min_sv_length - 1);
+    let mut summary = Summary::new();
+    let (index, ref_map) = closures::build_index(&opt.reference, &params, threads, threads, is_fasta_filename(&opt.reference), &Mask::new(), &HashSet::new(), &mut summary);
+
+    // Breakpoints of each read
+    let map_read = |q_str: &[u8], q_id: &str| -> Vec<Breakpoint> {
+        let mut segments = asm::find_blocks(q_id, q_str, &params, &index, LOOKBACK, opt.min_seeds, opt.min_length);
+        segments.retain(|b| b.primary);
+        segments.sort_unstable_by_key(|b| b.q_start);
+        read_breakpoints(&segments, opt.min_sv_length, 0)
+    };
+    let mut breakpoints = Vec::<Breakpoint>::new();
+    let mut nb_reads = 0;
+    let mut add_read = |found: &mut Vec<Breakpoint>| {
+        for b in found.iter_mut() {b.read = nb_reads;}
+        breakpoints.append(found);
+        nb_reads += 1;
+        None::<()>
+    };
+    let start = Instant::now();
+    let (buf, _) = get_reader(&opt.reads);
+    if is_fasta_filename(&opt.reads) {
+        let reader = seq_io::fasta::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fasta_records(reader, threads as u32, params.q, |record, found| {*found = map_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, found| {add_read(found)}).ok();
+    }
+    else {
+        let reader = seq_io::fastq::Reader::with_capacity(buf, 64*1024*params.b);
+        read_process_fastq_records(reader, threads as u32, params.q, |record, found| {*found = map_read(&record.seq().to_ascii_uppercase(), record.id().unwrap());}, |_record, found| {add_read(found)}).ok();
+    }
+    println!("Mapped {} reads in {:?}, found {} breakpoints.", nb_reads, start.elapsed(), breakpoints.len());
+
+    // Cluster breakpoints across reads and write calls sorted by position
+    let clusters = cluster_breakpoints(&mut breakpoints, opt.cluster_distance, opt.min_support);
+    let mut calls = HashMap::<SvType, usize>::new();
+    let mut records = Vec::<(String, usize, String)>::new();
+    for (i, c) in clusters.iter().enumerate() {
+        *calls.entry(c[0].sv_type).or_insert(0) += 1;
+        for r in vcf_records(i + 1, c, &ref_map) {
+            let f = r.split('\t').take(2).collect::<Vec<&str>>();
+            records.push((f[0].to_string(), f[1].parse().unwrap(), r.clone()));
+        }
+    }
+    records.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    let vcf_filename = format!("{}.vcf", opt.prefix);
+    let mut vcf_file = match File::create(&vcf_filename) {
+        Err(why) => panic!("Couldn't create {}: {}", vcf_filename, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    let mut contigs = ref_map.iter().map(|r| (r.name.clone(), r.len)).collect::<Vec<(String, usize)>>();
+    contigs.sort_unstable();
+    let mut header = String::from("##fileformat=VCFv4.2\n");
+    header.push_str(&format!("##source=mapquik-{}\n", env!("CARGO_PKG_VERSION")));
+    for (name, len) in contigs.iter() {header.push_str(&format!("##contig=<ID={},length={}>\n", name, len));}
+    for (id, desc) in [("DEL", "Deletion"), ("INS", "Insertion"), ("INV", "Inversion"), ("DUP", "Tandem duplication")].iter() {header.push_str(&format!("##ALT=<ID={},Description=\"{}\">\n", id, desc));}
+    header.push_str("##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">\n");
+    header.push_str("##INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of the variant\">\n");
+    header.push_str("##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length of the variant (negative for deletions)\">\n");
+    header.push_str("##INFO=<ID=MATEID,Number=1,Type=String,Description=\"ID of the mate breakend\">\n");
+    header.push_str("##INFO=<ID=SUPPORT,Number=1,Type=Integer,Description=\"Number of supporting reads\">\n");
+    header.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+    vcf_file.write_all(header.as_bytes()).expect("Error writing VCF header.");
+    for (_, _, r) in records.iter() {writeln!(vcf_file, "{}", r).expect("Error writing line.");}
+    let mut counts = calls.iter().map(|(t, n)| format!("{} {}", n, t.name())).collect::<Vec<String>>();
+    counts.sort_unstable();
+    println!("Called {} SVs supported by at least {} reads ({}).", clusters.len(), opt.min_support, counts.join(", "));
+    println!("Wrote calls to {}.", vcf_filename);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(r_idx: usize, q_start: usize, q_end: usize, r_start: usize, r_end: usize) -> Block {
+        Block {r_idx, rc: false, q_start, q_end, r_start, r_end, score: 10, identity: 1.0, mapq: 60, primary: true, matches: Vec::new()}
+    }
+
+    fn ref_map() -> DashMap<usize, RefInfo> {
+        let ref_map = DashMap::new();
+        ref_map.insert(0, RefInfo::new("chr1", 100000, false, false, 0));
+        ref_map.insert(1, RefInfo::new("chr2", 100000, false, false, 0));
+        ref_map
+    }
+
+    fn del(pos1: usize, read: usize) -> Breakpoint {
+        Breakpoint {sv_type: SvType::Del, r1: 0, pos1, r2: 0, pos2: pos1 + 500, len: 500, rc1: false, rc2: false, read}
+    }
+
+    #[test]
+    fn deletion_vcf_positions_are_1_based() {
+        let bps = read_breakpoints(&[segment(0, 0, 1000, 10000, 11000), segment(0, 1000, 2000, 11500, 12500)], 50, 0);
+        assert_eq!(bps.len(), 1);
+        assert_eq!((bps[0].sv_type, bps[0].pos1, bps[0].pos2, bps[0].len), (SvType::Del, 11000, 11500, 500));
+        let records = vcf_records(0, &bps, &ref_map());
+        assert_eq!(records, vec!["chr1\t11001\tmapquik.DEL.0\tN\t<DEL>\t.\tPASS\tSVTYPE=DEL;END=11501;SVLEN=-500;SUPPORT=1"]);
+    }
+
+    #[test]
+    fn insertion_vcf_end_equals_pos() {
+        let bps = read_breakpoints(&[segment(0, 0, 1000, 10000, 11000), segment(0, 1300, 2300, 11000, 12000)], 50, 0);
+        assert_eq!(bps.len(), 1);
+        assert_eq!((bps[0].sv_type, bps[0].pos1, bps[0].len), (SvType::Ins, 11000, 300));
+        let records = vcf_records(0, &bps, &ref_map());
+        assert_eq!(records, vec!["chr1\t11001\tmapquik.INS.0\tN\t<INS>\t.\tPASS\tSVTYPE=INS;END=11001;SVLEN=300;SUPPORT=1"]);
+    }
+
+    #[test]
+    fn breakend_mates_use_1_based_positions() {
+        let bps = read_breakpoints(&[segment(0, 0, 1000, 10000, 11000), segment(1, 1000, 2000, 5000, 6000)], 50, 0);
+        assert_eq!(bps.len(), 1);
+        assert_eq!((bps[0].sv_type, bps[0].pos1, bps[0].pos2), (SvType::Bnd, 11000, 5000));
+        let records = vcf_records(0, &bps, &ref_map());
+        assert_eq!(records, vec![
+            "chr1\t11001\tmapquik.BND.0_1\tN\tN[chr2:5001[\t.\tPASS\tSVTYPE=BND;MATEID=mapquik.BND.0_2;SUPPORT=1",
+            "chr2\t5001\tmapquik.BND.0_2\tN\t]chr1:11001]N\t.\tPASS\tSVTYPE=BND;MATEID=mapquik.BND.0_1;SUPPORT=1",
+        ]);
+    }
+
+    #[test]
+    fn small_gaps_are_not_breakpoints() {
+        let bps = read_breakpoints(&[segment(0, 0, 1000, 10000, 11000), segment(0, 1000, 2000, 11049, 12049)], 50, 0);
+        assert!(bps.is_empty());
+    }
+
+    #[test]
+    fn clusters_break_beyond_cluster_distance() {
+        let clusters = cluster_breakpoints(&mut [del(1000, 0), del(1500, 1)], 500, 1);
+        assert_eq!(clusters.len(), 1);
+        let clusters = cluster_breakpoints(&mut [del(1000, 0), del(1501, 1)], 500, 1);
+        assert_eq!(clusters.len(), 2);
+        // clusters supported by fewer reads than min_support are dropped
+        let clusters = cluster_breakpoints(&mut [del(1000, 0), del(1501, 1), del(1600, 2)], 500, 2);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].iter().map(|b| b.pos1).collect::<Vec<usize>>(), vec![1501, 1600]);
+    }
+}
@@ -0,0 +1,161 @@
+// tune.rs
+// Contains the "tune" subcommand, which maps a read sample over a grid of (k, l, density) values and recommends the best setting
+// (replaces the experiments/figure-k-l sweep scripts).
+
+use crate::{closures, eval::{self, Evaluation}, index::Entry, is_fasta_filename, mask::Mask, paf, presets::PresetOpt, simulate::{self, SimulateOpt}, summary::Summary};
+use rust_seq2kminmers::{FH, KH};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct TuneOpt {
+    /// Reference genome input
+    ///
+    #[structopt(parse(from_os_str), long)]
+    pub reference: PathBuf,
+    /// Read sample (FASTA/FASTQ, raw or compressed)
+    ///
+    /// If reads have true positions in their names (see
+    /// the eval subcommand), settings are ranked by correct
+    /// reads at MAPQ 60, otherwise by mapped reads at MAPQ
+    /// 60. If not given, reads are simulated from the
+    /// reference.
+    #[structopt(parse(from_os_str), long)]
+    pub reads: Option<PathBuf>,
+    /// Number of reads to simulate if no read sample is given
+    ///
+    #[structopt(long, default_value = "2000")]
+    pub nb_reads: usize,
+    /// Output prefix
+    ///
+    /// Mappings of each setting are written to
+    /// <prefix>.k<k>-l<l>-d<d>.paf, and the table of all
+    /// settings to <prefix>.tune.tsv.
+    #[structopt(short, long, default_value = "mapquik-tune")]
+    pub prefix: String,
+    /// Comma-separated k values
+    ///
+    #[structopt(short, long, default_value = "3,4,5,6,7")]
+    pub k: String,
+    /// Comma-separated l values
+    ///
+    #[structopt(short, long, default_value = "21,31")]
+    pub l: String,
+    /// Comma-separated density values
+    ///
+    #[structopt(short, long, default_value = "0.005,0.01,0.02")]
+    pub density: String,
+    #[structopt(flatten)]
+    pub preset: PresetOpt,
+    /// Minimum chain length
+    ///
+    #[structopt(short, long)]
+    pub chain: Option<usize>,
+    /// Minimum number of matching seeds
+    ///
+    #[structopt(short, long)]
+    pub seed: Option<usize>,
+    /// Maximum indexing + mapping time of the sample (seconds)
+    ///
+    #[structopt(long)]
+    pub max_time: Option<f64>,
+    /// Maximum estimated index memory (GB)
+    ///
+    /// Estimated from the number of indexed k-min-mers,
+    /// as the process RSS cannot be measured per setting.
+    #[structopt(long)]
+    pub max_memory: Option<f64>,
+    /// Minimum overlap between true and mapped intervals
+    ///
+    #[structopt(long, default_value = "0.1")]
+    pub min_overlap: f64,
+}
+
+// Outcome of mapping the sample with one (k, l, density) setting.
+pub struct TuneResult {
+    pub k: usize,
+    pub l: usize,
+    pub density: FH,
+    pub summary: Summary,
+    pub memory_gb: f64, // estimated index memory
+    pub mapped_q60: usize,
+    pub wrong_q60: usize,
+    pub score: usize, // correct (or mapped, without truth) reads at MAPQ 60
+}
+
+impl TuneResult {
+    pub fn time_s(&self) -> f64 {
+        self.summary.index_duration.as_secs_f64() + self.summary.map_duration.as_secs_f64()
+    }
+
+    pub fn within_budget(&self, opt: &TuneOpt) -> bool {
+        opt.max_time.map_or(true, |t| self.time_s() <= t) && opt.max_memory.map_or(true, |m| self.memory_gb <= m)
+    }
+}
+
+// Approximate memory of an index holding this many k-min-mers (keys and entries, without hash table overhead).
+pub fn index_memory_gb(nb_kminmers: usize) -> f64 {
+    (nb_kminmers * (std::mem::size_of::<KH>() + std::mem::size_of::<Entry>())) as f64 / 1024.0 / 1024.0 / 1024.0
+}
+
+// Run the tune subcommand.
+pub fn run(opt: &TuneOpt) {
+    let reads = match &opt.reads {
+        Some(r) => r.clone(),
+        None => {
+            let reads_path = format!("{}.reads.fq", opt.prefix);
+            let nb_reads = opt.nb_reads.to_string();
+            let sim_opt = SimulateOpt::from_iter(["simulate", opt.reference.to_str().unwrap(), "-o", &reads_path, "--nb-reads", &nb_reads]);
+            simulate::run(&sim_opt);
+            PathBuf::from(reads_path)
+        }
+    };
+    let reads_are_fasta = is_fasta_filename(&reads);
+    let ref_is_fasta = is_fasta_filename(&opt.reference);
+    let mask = Mask::new();
+    let circular_names = HashSet::<String>::new();
+    let preset = opt.preset.get("hifi");
+    let threads = opt.preset.threads;
+    let mut results = Vec::<TuneResult>::new();
+    for k in eval::parse_bins(&opt.k).iter().map(|x| *x as usize) {
+        for l in eval::parse_bins(&opt.l).iter().map(|x| *x as usize) {
+            for density in eval::parse_bins(&opt.density).iter().map(|x| *x as FH) {
+                println!("----- Running for k={}, l={}, d={} -----", k, l, density);
+                let mut params = opt.preset.to_params("hifi", k, l, density);
+                params.c = opt.chain.unwrap_or(preset.c);
+                params.s = opt.seed.unwrap_or(preset.s);
+                let setting_prefix = PathBuf::from(format!("{}.k{}-l{}-d{}", opt.prefix, k, l, density));
+                let summary = closures::run_mers(&reads, &opt.reference, &params, threads, threads, threads, params.q, reads_are_fasta, ref_is_fasta, &setting_prefix, &mask, &circular_names);
+                let records = paf::read_records(&PathBuf::from(format!("{}.paf", setting_prefix.to_str().unwrap())));
+                let evaluation = Evaluation::new(&records, Some(&reads), opt.min_overlap);
+                let (mapped_q60, wrong_q60) = evaluation.mapped_at(60);
+                // without true positions, count all reads mapped at MAPQ 60
+                let score = if evaluation.reads.is_empty() {records.iter().filter(|r| r.primary && r.mapq >= 60).count()} else {mapped_q60 - wrong_q60};
+                let memory_gb = index_memory_gb(summary.nb_unique + summary.nb_repeated);
+                results.push(TuneResult {k, l, density, summary, memory_gb, mapped_q60, wrong_q60, score});
+            }
+        }
+    }
+    let tsv_path = format!("{}.tune.tsv", opt.prefix);
+    let mut f = match File::create(&tsv_path) {
+        Err(why) => panic!("Couldn't create {}: {}", tsv_path, why),
+        Ok(f) => BufWriter::new(f),
+    };
+    writeln!(f, "k\tl\tdensity\tunique_kminmers\trepeated_kminmers\tindex_memory_gb\tindex_time_s\tmap_time_s\treads\tmapped\tmapped_q60\twrong_q60\tscore\twithin_budget").expect("Error writing tuning table.");
+    for r in results.iter() {
+        writeln!(f, "{}\t{}\t{}\t{}\t{}\t{:.3}\t{:.3}\t{:.3}\t{}\t{}\t{}\t{}\t{}\t{}", r.k, r.l, r.density, r.summary.nb_unique, r.summary.nb_repeated, r.memory_gb,
+                 r.summary.index_duration.as_secs_f64(), r.summary.map_duration.as_secs_f64(), r.summary.nb_reads.load(Ordering::Relaxed),
+                 r.summary.nb_mapped.load(Ordering::Relaxed), r.mapped_q60, r.wrong_q60, r.score, r.within_budget(opt)).expect("Error writing tuning table.");
+    }
+    println!("Wrote results of {} settings to {}.", results.len(), tsv_path);
+    // best score within budget, ties broken by runtime
+    let best = results.iter().filter(|r| r.within_budget(opt)).max_by(|a, b| a.score.cmp(&b.score).then(b.time_s().partial_cmp(&a.time_s()).unwrap()));
+    match best {
+        Some(r) => println!("Recommended parameters: -k {} -l {} -d {} ({} reads at MAPQ 60, {:.2}s, ~{:.2}GB index).", r.k, r.l, r.density, r.score, r.time_s(), r.memory_gb),
+        None => println!("No setting is within the time/memory budget."),
+    }
+}